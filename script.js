@@ -1,4 +1,4 @@
-import init, {output_highlight_span, init_panic_hook, emulate, EmulatorState}  from "./pkg/urcl_rs.js"
+import init, {output_highlight_span, init_panic_hook, emulate, EmulatorState, ColorMode}  from "./pkg/urcl_rs.js"
 import { StepResult } from "./pkg/urcl_rs.js";
 import { EditorWindow } from "./src/web/editor/editor.js";
 
@@ -45,6 +45,8 @@ export function clear_text() {
 
 export function in_text() { // needs to have a null terminate character if null terminate box is pressed
     // like stdin
+    const text = prompt("Program input:");
+    return text === null ? "" : text + "\n";
 }
 
 export function out_text(text) {
@@ -100,8 +102,113 @@ export function out_screen(width, height, pixels) {
     screen_ctx.putImageData(image_data, 0, 0);
 }
 
-export function output_registers(regs) {
+/** @type {AudioContext | undefined} */
+let audio;
+/** @type {AudioScheduledSourceNode | undefined} */
+let voice;
+/** @type {Set<AudioScheduledSourceNode>} */
+const voices = new Set();
+// the audio time a cycle of the program lines up with, so notes from one frame keep their spacing
+/** @type {{cycle: number, time: number} | undefined} */
+let sound_origin;
+let sound_clock_speed = 10000000;
+// how far ahead of the audio clock notes get scheduled, a frame is about 16ms
+const SOUND_LATENCY = 0.05;
+const WAVEFORMS = ["square", "triangle", "sine", "sawtooth"];
+
+function stop_sound() {
+    for (const scheduled of voices) {
+        scheduled.stop();
+    }
+    voices.clear();
+    voice = undefined;
+    sound_origin = undefined;
+}
 
+/**
+ * the program changed what is playing, notes are midi numbers and 0 is silence
+ * @param {number} cycle
+ * @param {number} note
+ * @param {number} instrument
+ */
+export function out_sound(cycle, note, instrument) {
+    audio ??= new AudioContext();
+    let time = sound_origin ? sound_origin.time + (cycle - sound_origin.cycle) / sound_clock_speed : -1;
+    // line the clocks up again after a pause or when the program fell behind
+    if (time < audio.currentTime || time > audio.currentTime + 1) {
+        sound_origin = {cycle, time: audio.currentTime + SOUND_LATENCY};
+        time = sound_origin.time;
+    }
+    voice?.stop(time);
+    voice = undefined;
+    if (note === 0) {
+        return;
+    }
+    const frequency = 440 * Math.pow(2, (note - 69) / 12);
+    const gain = audio.createGain();
+    gain.gain.value = 0.1;
+    gain.connect(audio.destination);
+    if (instrument < WAVEFORMS.length) {
+        const oscillator = audio.createOscillator();
+        oscillator.type = WAVEFORMS[instrument];
+        oscillator.frequency.value = frequency;
+        voice = oscillator;
+    } else {
+        // noise, played faster for higher notes
+        const buffer = audio.createBuffer(1, audio.sampleRate, audio.sampleRate);
+        buffer.getChannelData(0).forEach((_, i, data) => data[i] = Math.random() * 2 - 1);
+        const source = audio.createBufferSource();
+        source.buffer = buffer;
+        source.loop = true;
+        source.playbackRate.value = frequency / 440;
+        voice = source;
+    }
+    const scheduled = voice;
+    scheduled.onended = () => voices.delete(scheduled);
+    voices.add(scheduled);
+    scheduled.connect(gain);
+    scheduled.start(time);
+}
+
+const MEMORY_PAGE_SIZE = 64;
+const MEMORY_ROW_SIZE = 8;
+
+/**
+ * @param {HTMLTableElement} table
+ * @param {string[][]} rows
+ */
+function fill_table(table, rows) {
+    table.replaceChildren(...rows.map(cells => {
+        const row = document.createElement("tr");
+        row.replaceChildren(...cells.map(text => {
+            const cell = document.createElement("td");
+            cell.textContent = text;
+            return cell;
+        }));
+        return row;
+    }));
+}
+
+// only the registers and the memory page in view are read out of the emulator
+export function output_registers() {
+    if (!emulator || !document.getElementById("debug_option").checked) {
+        return;
+    }
+    const regs = [["PC", emulator.get_pc().toString(), emulator.current_instruction() ?? ""], ["SP", emulator.get_sp().toString(), ""]];
+    emulator.get_regs().forEach((value, i) => regs.push(["r" + (i + 1), value.toString(), ""]));
+    fill_table(by_id(HTMLTableElement, "registers"), regs);
+
+    const page_input = by_id(HTMLInputElement, "mem_page");
+    const pages = Math.max(1, Math.ceil(emulator.memory_len() / MEMORY_PAGE_SIZE));
+    const page = Math.min(Math.max(0, Number(page_input.value) || 0), pages - 1);
+    page_input.value = page.toString();
+    const start = page * MEMORY_PAGE_SIZE;
+    const words = emulator.get_memory(start, MEMORY_PAGE_SIZE);
+    const rows = [];
+    for (let i = 0; i < words.length; i += MEMORY_ROW_SIZE) {
+        rows.push(["M" + (start + i), ...Array.from(words.slice(i, i + MEMORY_ROW_SIZE), v => v.toString())]);
+    }
+    fill_table(by_id(HTMLTableElement, "memory"), rows);
 }
 
 export async function clear_span() {
@@ -125,29 +232,124 @@ let emulator;
 /** @type {undefined | number} */
 let frame_id;
 
+// what the program stored through %BUS, kept in indexeddb between visits
+let storage_bytes;
+
+function open_storage_db() {
+    return new Promise((resolve, reject) => {
+        const request = indexedDB.open("urcl-rs", 1);
+        request.onupgradeneeded = () => request.result.createObjectStore("storage");
+        request.onsuccess = () => resolve(request.result);
+        request.onerror = () => reject(request.error);
+    });
+}
+
+async function load_storage() {
+    const db = await open_storage_db();
+    return new Promise((resolve, reject) => {
+        const request = db.transaction("storage").objectStore("storage").get("bus");
+        request.onsuccess = () => resolve(request.result);
+        request.onerror = () => reject(request.error);
+    });
+}
+
+/**
+ * @param {Uint8Array} bytes
+ */
+async function save_storage(bytes) {
+    storage_bytes = bytes;
+    const db = await open_storage_db();
+    db.transaction("storage", "readwrite").objectStore("storage").put(bytes, "bus");
+}
+
 /**
  * 
  * @param {string} source 
  */
 function start_emulation(source) {
+    stop_sound();
     emulator = emulate(source);
+    const width = Number(document.getElementById("screen_width").value);
+    const height = Number(document.getElementById("screen_height").value);
+    if (emulator && width > 0 && height > 0) {
+        emulator.set_screen_size(width, height);
+    }
+    if (emulator) {
+        emulator.set_color_mode(ColorMode[document.getElementById("color_mode").value]);
+    }
+    const clock_speed = Number(document.getElementById("clock_speed").value);
+    if (emulator && clock_speed >= 1) {
+        emulator.set_clock_speed(clock_speed);
+    }
+    if (emulator) {
+        sound_clock_speed = emulator.get_clock_speed();
+    }
+    if (emulator && storage_bytes) {
+        emulator.load_storage(storage_bytes);
+    }
+    if (emulator && document.getElementById("debug_option").checked) {
+        emulator.set_journal_capacity(10000);
+    }
     continue_emulation();
 }
 
+/**
+ * @param {MouseEvent} event
+ */
+function forward_mouse(event) {
+    if (!emulator) {
+        return;
+    }
+    // the canvas is scaled up so go back to screen pixels
+    const x = Math.floor(event.offsetX * screen_canvas.width / screen_canvas.clientWidth);
+    const y = Math.floor(event.offsetY * screen_canvas.height / screen_canvas.clientHeight);
+    emulator.mouse_move(x, y);
+    emulator.mouse_buttons(event.buttons);
+}
+
+function forward_gamepads() {
+    if (!emulator || !navigator.getGamepads) {
+        return;
+    }
+    for (const pad of navigator.getGamepads()) {
+        if (!pad) {
+            continue;
+        }
+        let buttons = 0;
+        pad.buttons.forEach((button, i) => { if (button.pressed && i < 32) buttons |= 1 << i; });
+        emulator.set_gamepad(pad.index, buttons >>> 0, new Float64Array(pad.axes));
+    }
+}
+
 function continue_emulation() {
     cancel_emulation();
     if (!emulator) {
         return;
     }
-    const result = emulator.run_for_ms(16);
-    if (result === StepResult.Continue) {
+    forward_gamepads();
+    let result = emulator.run_for_ms(16);
+    output_registers();
+    if (result === StepResult.Input) {
+        const text = in_text();
+        if (text !== "") {
+            emulator.push_input(text);
+            result = StepResult.Continue;
+        }
+    }
+    if (result === StepResult.Continue || result === StepResult.Waiting) {
         frame_id = requestAnimationFrame(continue_emulation);
         pause_button.textContent = "PAUSE";
         pause_button.disabled = false;
+    } else if (result === StepResult.Breakpoint) {
+        pause_button.textContent = "CONTINUE";
+        pause_button.disabled = false;
+        save_storage(emulator.export_storage()).catch(console.error);
     } else {
         pause_button.textContent = "DONE";
         pause_button.disabled = true;
+        stop_sound();
         if (emulator) {
+            save_storage(emulator.export_storage()).catch(console.error);
             emulator.free();
         } 
         emulator = undefined;
@@ -162,6 +364,7 @@ function cancel_emulation() {
 
 init().then(() => { // all code should go in here
     init_panic_hook();
+    load_storage().then(bytes => storage_bytes = bytes).catch(console.error);
 
     const is_chromium = !!window.chrome;
 
@@ -170,6 +373,8 @@ init().then(() => { // all code should go in here
             pause_button.textContent = "CONTINUE";
             pause_button.disabled = false;
             cancel_emulation();
+            stop_sound();
+            save_storage(emulator.export_storage()).catch(console.error);
         } else if (emulator) {
             continue_emulation();
         } else {
@@ -188,6 +393,20 @@ init().then(() => { // all code should go in here
     document.getElementById("document_link").onclick    = function() { window.open("https://github.com/ModPunchtree/URCL/releases/latest", "_blank"); };
     document.getElementById("emulate").onclick          = function() { start_emulation(code_input.value); };
     document.getElementById("clear").onclick            = function() { clear_text(); };
+    document.getElementById("step_in").onclick          = function() { if (emulator) { emulator.step_in(); continue_emulation(); } };
+    document.getElementById("step_over").onclick        = function() { if (emulator) { emulator.step_over(); continue_emulation(); } };
+    document.getElementById("step_out").onclick         = function() { if (emulator) { emulator.step_out(); continue_emulation(); } };
+    document.getElementById("step_back").onclick        = function() { if (emulator && emulator.step_back()) { cancel_emulation(); emulator.show(); output_registers(); pause_button.textContent = "CONTINUE"; } };
+    screen_canvas.onmousemove                           = forward_mouse;
+    screen_canvas.onmousedown                           = forward_mouse;
+    screen_canvas.onmouseup                             = forward_mouse;
+    screen_canvas.onwheel                               = function(event) { if (emulator) { event.preventDefault(); emulator.mouse_wheel(Math.sign(event.deltaY)); } };
+    screen_canvas.onkeydown                             = function(event) { if (emulator) { event.preventDefault(); emulator.key_down(event.keyCode); } };
+    screen_canvas.onkeyup                               = function(event) { if (emulator) { event.preventDefault(); emulator.key_up(event.keyCode); } };
+    window.addEventListener("gamepaddisconnected", event => { if (emulator) emulator.remove_gamepad(event.gamepad.index); });
+    document.getElementById("mem_prev").onclick         = function() { const page = by_id(HTMLInputElement, "mem_page"); page.value = Math.max(0, Number(page.value) - 1).toString(); output_registers(); };
+    document.getElementById("mem_next").onclick         = function() { const page = by_id(HTMLInputElement, "mem_page"); page.value = (Number(page.value) + 1).toString(); output_registers(); };
+    document.getElementById("mem_page").onchange        = function() { output_registers(); };
     document.getElementById("debug_option").onchange    = function() { update_debug_buttons(this.checked); };
     document.getElementById("tab_size").onchange        = function() { document.querySelector(":root").style.setProperty("--tab-size", this.value); };
     document.getElementsByTagName("body")[0].onresize   = function() { resync_element_size(); };
@@ -226,6 +445,8 @@ init().then(() => { // all code should go in here
         localStorage.setItem("debug_option", document.getElementById("debug_option").checked ? "t" : "f");
         localStorage.setItem("screen_width", document.getElementById("screen_width").value);
         localStorage.setItem("screen_height", document.getElementById("screen_height").value);
+        localStorage.setItem("color_mode", document.getElementById("color_mode").value);
+        localStorage.setItem("clock_speed", document.getElementById("clock_speed").value);
     };
 
     for (let i = 0; i < document.getElementsByClassName("example_link").length; i++) {
@@ -242,6 +463,8 @@ init().then(() => { // all code should go in here
 
     document.getElementById("screen_width") .value = localStorage.getItem("screen_width")  == null ? 32 : localStorage.getItem("screen_width");
     document.getElementById("screen_height").value = localStorage.getItem("screen_height") == null ? 32 : localStorage.getItem("screen_height");
+    document.getElementById("color_mode").value = localStorage.getItem("color_mode") == null ? "Rgb888" : localStorage.getItem("color_mode");
+    document.getElementById("clock_speed").value = localStorage.getItem("clock_speed") == null ? 10000000 : localStorage.getItem("clock_speed");
 
     const params = new URLSearchParams(window.location.search);
 
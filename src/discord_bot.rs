@@ -16,8 +16,21 @@ impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
         if msg.content.starts_with("!emu") {
             let body;
+            let mut name = "main".to_string();
+            // every other attachment can be used with @include and read through %FILE
+            let mut files = sources::MemorySources::new();
+            let mut inputs = Vec::new();
             if msg.attachments.len() > 0 {
                 body = reqwest::get(msg.attachments[0].url.clone()).await.unwrap().text().await.unwrap();
+                name = msg.attachments[0].filename.clone();
+                for att in msg.attachments.iter().skip(1) {
+                    if let Ok(res) = reqwest::get(att.url.clone()).await {
+                        if let Ok(text) = res.text().await {
+                            inputs.push((att.filename.clone(), text.clone().into_bytes()));
+                            files.insert(att.filename.clone(), text);
+                        }
+                    }
+                }
             } else {
                 let tmp = msg.content.split("```").collect::<Vec<&str>>();
 
@@ -30,7 +43,7 @@ impl EventHandler for Handler {
                 body = tmp[1].to_string();
             }
 
-            let mut emu = match emulator::silence_emulate(body) {
+            let mut emu = match emulator::silence_emulate(&name, body, None, &mut files) {
                 Ok(emu) => emu,
                 Err(err) => {
                     if let Err(err) = msg.channel_id.say(&ctx.http, format!("Cannot compile URCL code: ```ansi\n{}```", err)).await {
@@ -40,6 +53,9 @@ impl EventHandler for Handler {
                 }
             };
 
+            for (name, bytes) in inputs {
+                emu.add_file(&name, bytes);
+            }
             let result = silence_run_for_ms(&mut emu, 1000.0);
             let mut att = Vec::<AttachmentType>::new();
 
@@ -49,7 +65,8 @@ impl EventHandler for Handler {
             let pixels = screen.pixels();
             let mut png = Image::new(width as u32, height as u32);
             for (i, el) in pixels.iter().enumerate() {
-                png.pixels[i] = RGBA24{r: (el >> 24) as u8, g: (el >> 16) as u8, b: (el >> 8) as u8, a: *el as u8}
+                let [r, g, b, a] = el.to_ne_bytes();
+                png.pixels[i] = RGBA24{r, g, b, a}
             }
             let mut png_file = Vec::<u8>::new();
             if let Err(err) = png.write_png(&mut png_file) {
@@ -59,6 +76,13 @@ impl EventHandler for Handler {
                 att.push((png_file.as_slice(), "image.png").into());
             }
 
+            // declared out here since att borrows it
+            let wav;
+            if emu.played_sound() {
+                wav = emu.render_sound(emu.get_clock_speed());
+                att.push((wav.as_slice(), "sound.wav").into());
+            }
+
             use emulator::emulator::StepResult;
             match result {
                 StepResult::HLT => {
@@ -73,6 +97,12 @@ impl EventHandler for Handler {
                         println!("\x1b[1;93mDiscord bot warning: Unable to send message, reason: {}\x1b[0;0m", err)
                     };
                 },
+                StepResult::Input => {
+                    let output = emu.get_output();
+                    if let Err(err) = msg.channel_id.send_files(&ctx.http, att, |m| m.content(format!("Program is waiting for input: ```\n{}```", output))).await {
+                        println!("\x1b[1;93mDiscord bot warning: Unable to send message, reason: {}\x1b[0;0m", err)
+                    };
+                },
                 StepResult::Error => {
                     let output = emu.get_output();
                     if let Err(err) = msg.channel_id.send_files(&ctx.http, att, |m| m.content(format!("Program exited with error: ```ansi\n{}```Output: ```\n{}```",
@@ -114,6 +144,8 @@ pub fn silence_run_for_ms(emu: &mut emulator::emulator::EmulatorState, max_time_
             let result = emu.step();
             match result {
                 StepResult::Continue => (),
+                // nobody is watching so theres no point waiting
+                StepResult::Waiting => emu.skip_wait(),
                 _ => {
                     return result;
                 }
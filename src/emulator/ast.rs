@@ -1,20 +1,32 @@
-use std::{collections::HashMap, str::FromStr, rc::Rc};
+use std::{collections::{HashMap, HashSet}, str::FromStr, rc::Rc, ops::Range};
 
-use super::{lexer::{Token, Kind, UToken}, errorcontext::{ErrorContext, ErrorKind}, devices::IOPort};
+use super::{lexer::{self, Token, Kind, UToken}, errorcontext::{ErrorContext, ErrorKind}, devices::IOPort, sources::SourceMap};
 
 struct TokenBuffer<'a> {
     index: usize,
-    toks: Vec<UToken<'a>>
+    toks: Vec<UToken<'a>>,
+    // which macro expansion each token came from
+    origin: Vec<Option<usize>>,
 }
 impl <'a> TokenBuffer<'a> {
     #[inline]
     pub fn new(toks: Vec<UToken<'a>>) -> Self {
         TokenBuffer {
+            origin: vec![None; toks.len()],
             toks: toks,
             index: 0,
         }
     }
     #[inline]
+    pub fn expansion(&self) -> Option<usize> {
+        self.origin.get(self.index).copied().flatten()
+    }
+    pub fn splice(&mut self, range: Range<usize>, toks: Vec<(UToken<'a>, Option<usize>)>) {
+        let (toks, origin): (Vec<_>, Vec<_>) = toks.into_iter().unzip();
+        self.toks.splice(range.clone(), toks);
+        self.origin.splice(range, origin);
+    }
+    #[inline]
     pub fn has_next(&self) -> bool {
         self.index < self.toks.len()
     }
@@ -30,6 +42,21 @@ impl <'a> TokenBuffer<'a> {
         self.advance();
         self.toks[self.index].clone()
     }
+    // the raw token after the current one, whitespace and comments included
+    #[inline]
+    pub fn peek(&self) -> Option<&UToken<'a>> {
+        self.toks.get(self.index + 1)
+    }
+    // moves one raw token forward without skipping anything
+    #[inline]
+    pub fn bump(&mut self) {
+        self.index += 1;
+    }
+    // puts the current token back, so the next advance lands on it again
+    #[inline]
+    pub fn unget(&mut self) {
+        self.index = self.index.saturating_sub(1);
+    }
     #[inline]
     pub fn current(&self) -> UToken<'a> {
         if self.has_next() {
@@ -38,6 +65,17 @@ impl <'a> TokenBuffer<'a> {
             Token {kind: Kind::EOF, str: ""}
         }
     }
+    // looks past whitespace, comments and other labels for a dw
+    pub fn label_before_dw(&self) -> bool {
+        for tok in self.toks[self.index+1..].iter() {
+            match tok.kind {
+                Kind::White | Kind::Comment | Kind::LF | Kind::Label => {},
+                Kind::Name => return tok.str.eq_ignore_ascii_case("dw"),
+                _ => return false,
+            }
+        }
+        false
+    }
     pub fn cur(&self) -> &UToken<'a> {
         if self.has_next() {
             &self.toks[self.index]
@@ -52,21 +90,73 @@ pub struct Parser<'a> {
     pub err: ErrorContext<'a>,
     pub ast: Program,
     pub at_line: usize,
-    pub macros: HashMap<&'a str, UToken<'a>>
+    pub macros: HashMap<&'a str, Vec<UToken<'a>>>,
+    pub bits: Option<u64>,
+    pub macro_defs: HashMap<&'a str, MacroDef<'a>>,
+    sources: &'a SourceMap,
+    expansions: Vec<Expansion<'a>>,
+    // tokens put into the buffer by @define, @MACRO and @include so far
+    expanded: usize,
+    recursed: bool,
+    inst_start: usize,
+}
+
+pub struct MacroDef<'a> {
+    params: Vec<&'a str>,
+    body: Vec<UToken<'a>>,
+    // labels defined in the body, renamed for every expansion
+    locals: HashSet<&'a str>,
 }
 
-pub fn gen_ast<'a>(toks: Vec<UToken<'a>>, src: Rc<str>) -> Parser<'a> {
+// a macro call or an @include, whose tokens were put into the buffer
+struct Expansion<'a> {
+    call: UToken<'a>,
+    parent: Option<usize>,
+    depth: usize,
+    locals: HashSet<&'a str>,
+    file: Option<usize>,
+}
+
+const MAX_MACRO_DEPTH: usize = 64;
+// depth alone doesnt stop a macro that calls itself twice per line from doubling 64 times
+const MAX_EXPANDED_TOKENS: usize = 1 << 20;
+
+pub fn gen_ast<'a>(sources: &'a Rc<SourceMap>, bits: Option<u64>) -> Parser<'a> {
     let err = ErrorContext::new();
-    let ast = Program::new(src);
-    let buf = TokenBuffer::new(toks);
-    let mut p = Parser {buf, err, ast, at_line: 1, macros: HashMap::new() };
+    let mut ast = Program::new(sources.clone());
+    let buf = TokenBuffer::new(lexer::lex(sources.src(0)));
+    let bits = bits.map(|v| v.clamp(1, 64));
+    if let Some(bits) = bits {
+        ast.headers.bits = bits;
+    }
+    let mut p = Parser {buf, err, ast, at_line: 1, macros: HashMap::new(), bits, macro_defs: HashMap::new(), sources: sources.as_ref(), expansions: Vec::new(), expanded: 0, recursed: false, inst_start: 0 };
 
     while p.buf.has_next() {
+        p.err.expansion = p.macro_call(p.buf.expansion());
         match p.buf.current().kind {
             Kind::Name => {
+                if let Some((&name, _)) = p.macro_defs.get_key_value(p.buf.current().str) {
+                    p.expand_macro(name);
+                    continue;
+                }
+                p.inst_start = p.buf.index;
                 match p.buf.current().str.to_lowercase().as_str() {
                     "bits" => {
-                        p.ast.headers.bits = match p.buf.next().kind { Kind::Int(v) => v as u64, _ => match p.buf.next().kind {Kind::Int(v) => v as u64, _ => continue} };
+                        let cmp = match p.buf.next().kind {
+                            Kind::Eq => {p.buf.advance(); BitsCmp::Eq},
+                            Kind::GE => {p.buf.advance(); BitsCmp::GE},
+                            Kind::LE => {p.buf.advance(); BitsCmp::LE},
+                            _ => BitsCmp::Eq,
+                        };
+                        let value = match p.buf.current().kind {Kind::Int(v) => v as u64, _ => {continue;}};
+                        let bits = p.bits.unwrap_or(cmp.pick(value));
+                        if !cmp.accepts(value, bits) {
+                            p.err.error(&p.buf.current(), ErrorKind::BitsMismatch{cmp, value, bits});
+                        } else if bits == 0 || bits > 64 {
+                            p.err.error(&p.buf.current(), ErrorKind::UnsupportedBits(bits));
+                        }
+                        p.ast.headers.bits = bits;
+                        p.ast.headers.bits_constraint = Some((cmp, value));
                         p.buf.advance();
                     },
                     "minreg" => {
@@ -83,11 +173,8 @@ pub fn gen_ast<'a>(toks: Vec<UToken<'a>>, src: Rc<str>) -> Parser<'a> {
                     },
 
                     "dw" => {
-                        let mut data: Vec<u64> = match p.buf.next().kind {
-                            Kind::Int(v) => vec![v as u64],
-                            _ => {p.err.error(&p.buf.current(), ErrorKind::YoMamma); continue;},
-                        };
-                        p.ast.memory.append(&mut data);
+                        p.get_dw();
+                        p.assert_done();
                     },
 
                     "imm"     => inst(Inst::MOV(p.get_reg(), p.get_imm())           , &mut p),
@@ -163,13 +250,19 @@ pub fn gen_ast<'a>(toks: Vec<UToken<'a>>, src: Rc<str>) -> Parser<'a> {
                     "yomamma" => { p.err.error(&p.buf.current(), ErrorKind::YoMamma); p.buf.advance(); },
                     _ => { p.err.error(&p.buf.current(), ErrorKind::UnknownInstruction); p.buf.advance(); },
                 }
-                p.ast.debug.pc_to_line_start.push(p.at_line);
             },
             Kind::Label => {
-                match p.ast.labels.get(p.buf.current().str) {
+                let label_name = &p.label_key(p.buf.current().str, p.buf.index);
+                if !matches!(p.ast.labels.get(label_name), Some(Label::Defined(_))) && !p.buf.label_before_dw() {
+                    p.ast.debug.code_labels.push((p.ast.instructions.len(), label_name.clone()));
+                }
+                match p.ast.labels.get(label_name) {
                     Some(Label::Defined(_)) => p.err.error(&p.buf.current(), ErrorKind::DuplicatedLabelName),
                     Some(Label::Undefined(v)) => {
-                        let label_name = p.buf.current().str; let pc = p.ast.instructions.len();
+                        let pc = if p.buf.label_before_dw() {p.ast.memory.len()} else {p.ast.instructions.len()};
+                        for i in v.data_references.iter() {
+                            p.ast.memory[*i] = pc as u64;
+                        }
                         for i in v.references.iter() {
                             p.ast.instructions[*i] = match &p.ast.instructions[*i] {
                                 Inst::PSH(a) => Inst::PSH(a.clone().transform_label(label_name, pc)),
@@ -238,24 +331,26 @@ pub fn gen_ast<'a>(toks: Vec<UToken<'a>>, src: Rc<str>) -> Parser<'a> {
                                 _ => continue,
                             }
                         }
-                        p.ast.labels.insert(p.buf.current().str.to_string(), Label::Defined(p.ast.instructions.len()));
+                        p.ast.labels.insert(label_name.to_string(), Label::Defined(pc));
+                    },
+                    None => {
+                        let pc = if p.buf.label_before_dw() {p.ast.memory.len()} else {p.ast.instructions.len()};
+                        p.ast.labels.insert(label_name.to_string(), Label::Defined(pc));
                     },
-                    None => { p.ast.labels.insert(p.buf.current().str.to_string(), Label::Defined(p.ast.instructions.len())); },
                 }
                 p.buf.advance();
             },
             Kind::Macro => {
-                match p.buf.current().str {
-                    "@define" => {
-                        let to_replace = p.buf.next().str;
-                        p.macros.insert(to_replace, p.buf.next());
-                    },
+                match p.buf.current().str.to_lowercase().as_str() {
+                    "@define" => p.define(),
+                    "@macro" => p.macro_def(),
+                    "@include" => p.include(),
                     _ => {p.err.error(&p.buf.current(), ErrorKind::UnexpectedMacro); p.buf.advance()},
                 }
             }
             Kind::White | Kind::Comment | Kind::Char | Kind::String => p.buf.advance(),
             Kind::EOF => break,
-            Kind::LF => {p.at_line += 1; p.buf.advance()},
+            Kind::LF => {if p.buf.expansion().is_none() {p.at_line += 1}; p.buf.advance()},
             _ => { p.buf.advance(); },
         }
     }
@@ -264,6 +359,7 @@ pub fn gen_ast<'a>(toks: Vec<UToken<'a>>, src: Rc<str>) -> Parser<'a> {
         match el {
             Label::Undefined(a) => {
                 for i in a.referenced_tokens.iter() {
+                    p.err.expansion = p.macro_call(p.buf.origin[*i]);
                     p.err.error(&p.buf.toks[*i], ErrorKind::UndefinedLabel);
                 }
             },
@@ -276,10 +372,271 @@ pub fn gen_ast<'a>(toks: Vec<UToken<'a>>, src: Rc<str>) -> Parser<'a> {
 
 fn inst<'a>(inst: Inst, p: &mut Parser<'a>) {
     p.ast.instructions.push(inst);
+    p.ast.debug.pc_to_line_start.push(p.at_line);
+    // from the start of the mnemonic to the end of the last operand
+    let src = p.ast.debug.sources.text.as_ptr() as usize;
+    let start = p.buf.toks[p.inst_start].str.as_ptr() as usize - src;
+    let mut end = p.buf.cur().str.as_ptr() as usize + p.buf.cur().str.len() - src;
+    // operands passed into a macro live on a different line than the mnemonic
+    if end < start || p.ast.debug.sources.text[start..end].contains('\n') {
+        end = start + p.buf.toks[p.inst_start].str.len();
+    }
+    p.ast.debug.pc_to_span.push(start..end);
     p.assert_done();
 }
 
 impl <'a> Parser<'a> {
+    // labels defined inside a macro body get a unique name per expansion
+    fn label_key(&self, label: &str, index: usize) -> String {
+        match self.buf.origin.get(index).copied().flatten() {
+            Some(id) if self.expansions[id].locals.contains(label) => format!("{}@{}", label, id),
+            _ => label.to_string(),
+        }
+    }
+
+    // puts the value of a @define where its name was, so it can be any operand
+    fn expand_defines(&mut self) -> bool {
+        let mut depth = 0;
+        while let Some(value) = self.macros.get(self.buf.current().str).filter(|_| self.buf.current().kind == Kind::Name) {
+            let len = value.len();
+            if depth >= MAX_MACRO_DEPTH {
+                self.recursion_error(&self.buf.current());
+                return false;
+            }
+            if self.over_budget(len, &self.buf.current()) {
+                return false;
+            }
+            let value = &self.macros[self.buf.current().str];
+            let origin = self.buf.expansion();
+            let value = value.iter().map(|tok| (tok.clone(), origin)).collect();
+            let index = self.buf.index;
+            self.buf.splice(index..index+1, value);
+            if matches!(self.buf.current().kind, Kind::White | Kind::Comment) {
+                self.buf.advance();
+            }
+            depth += 1;
+        }
+        true
+    }
+
+    // a runaway macro fails at every call it left behind, one error is enough
+    fn recursion_error(&mut self, at: &UToken<'a>) {
+        if !std::mem::replace(&mut self.recursed, true) {
+            self.err.error(at, ErrorKind::MacroRecursion);
+        }
+    }
+    // counts tokens against MAX_EXPANDED_TOKENS, true once the budget is used up
+    fn over_budget(&mut self, tokens: usize, at: &UToken<'a>) -> bool {
+        self.expanded = self.expanded.saturating_add(tokens);
+        if self.expanded > MAX_EXPANDED_TOKENS {
+            self.recursion_error(at);
+            return true;
+        }
+        false
+    }
+
+    // call site of the macro a token was expanded from, included files don't count
+    fn macro_call(&self, origin: Option<usize>) -> Option<&'a str> {
+        let expansion = &self.expansions[origin?];
+        match expansion.file {
+            None => Some(expansion.call.str),
+            Some(_) => None,
+        }
+    }
+
+    fn include(&mut self) {
+        let inc_tok = self.buf.current();
+        self.buf.advance();
+        let path_tok = self.buf.current();
+        let path = match self.current_ast_op() {
+            AstOp::String(path) => path,
+            AstOp::Unknown => {self.assert_done(); return},
+            actual => {
+                self.err.error(&path_tok, ErrorKind::InvalidOperandType{expected: "file path", actual});
+                self.assert_done();
+                return;
+            },
+        };
+        self.assert_done();
+
+        // relative to the file the @include is written in
+        let sources = &self.ast.debug.sources;
+        let (from, _) = sources.locate(inc_tok.str.as_ptr() as usize - sources.text.as_ptr() as usize);
+        let Some(file) = sources.file(&sources.include_name(from, &path)) else {
+            self.err.error(&path_tok, ErrorKind::IncludeNotFound(path));
+            return;
+        };
+        let parent = self.buf.expansion();
+        let mut at = parent;
+        let mut cycle = file == 0;
+        while let Some(id) = at {
+            cycle |= self.expansions[id].file == Some(file);
+            at = self.expansions[id].parent;
+        }
+        if cycle {
+            self.err.error(&path_tok, ErrorKind::IncludeCycle(path));
+            return;
+        }
+        let depth = parent.map_or(0, |id| self.expansions[id].depth + 1);
+        if depth >= MAX_MACRO_DEPTH {
+            self.recursion_error(&inc_tok);
+            return;
+        }
+
+        // the included tokens go before the line feed that ends the @include
+        let id = self.expansions.len();
+        let sources = self.sources;
+        let toks: Vec<_> = lexer::lex(sources.src(file)).into_iter()
+            .filter(|tok| tok.kind != Kind::EOF)
+            .map(|tok| (tok, Some(id)))
+            .collect();
+        if self.over_budget(toks.len(), &inc_tok) {
+            return;
+        }
+        self.expansions.push(Expansion {call: inc_tok, parent, depth, locals: HashSet::new(), file: Some(file)});
+        let index = self.buf.index.min(self.buf.toks.len());
+        self.buf.splice(index..index, toks);
+    }
+
+    fn define(&mut self) {
+        let name = self.buf.next();
+        if name.kind != Kind::Name {
+            self.err.error(&name, ErrorKind::InvalidOperand);
+        }
+        self.buf.advance();
+        let start = self.buf.index.min(self.buf.toks.len());
+        while !matches!(self.buf.current().kind, Kind::LF | Kind::EOF) {
+            self.buf.advance();
+        }
+        let mut end = self.buf.index.min(self.buf.toks.len()).max(start);
+        while end > start && matches!(self.buf.toks[end-1].kind, Kind::White | Kind::Comment) {
+            end -= 1;
+        }
+        if name.kind == Kind::Name {
+            self.macros.insert(name.str, self.buf.toks[start..end].to_vec());
+        }
+    }
+
+    fn macro_def(&mut self) {
+        let def_tok = self.buf.current();
+        let name = self.buf.next();
+        if name.kind != Kind::Name {
+            self.err.error(&name, ErrorKind::InvalidOperand);
+        }
+        let mut params = Vec::new();
+        while !matches!(self.buf.next().kind, Kind::LF | Kind::EOF) {
+            let tok = self.buf.current();
+            if tok.kind == Kind::Name {
+                params.push(tok.str);
+            } else {
+                self.err.error(&tok, ErrorKind::InvalidOperand);
+            }
+        }
+
+        let len = self.buf.toks.len();
+        let body_start = (self.buf.index + 1).min(len);
+        let end = self.buf.toks[body_start..].iter()
+            .position(|tok| tok.kind == Kind::Macro && tok.str.eq_ignore_ascii_case("@end"))
+            .map(|i| body_start + i);
+        if end.is_none() {
+            self.err.error(&def_tok, ErrorKind::MacroNoEnding);
+        }
+        let body_end = end.unwrap_or(len);
+        for i in self.buf.index.min(len)..body_end {
+            if self.buf.toks[i].kind == Kind::LF && self.buf.origin[i].is_none() {
+                self.at_line += 1;
+            }
+        }
+
+        let body = self.buf.toks[body_start..body_end].to_vec();
+        let mut locals = HashSet::new();
+        let mut line_start = true;
+        for tok in body.iter() {
+            match tok.kind {
+                Kind::LF => line_start = true,
+                Kind::White | Kind::Comment => {},
+                Kind::Label if line_start => {locals.insert(tok.str);},
+                _ => line_start = false,
+            }
+        }
+        if name.kind == Kind::Name {
+            self.macro_defs.insert(name.str, MacroDef {params, body, locals});
+        }
+
+        self.buf.index = body_end;
+        if end.is_some() {
+            self.assert_done();
+        }
+    }
+
+    fn expand_macro(&mut self, name: &'a str) {
+        let call = self.buf.current();
+        let start = self.buf.index;
+        let parent = self.buf.expansion();
+        let depth = parent.map_or(0, |id| self.expansions[id].depth + 1);
+
+        let mut args = Vec::new();
+        self.buf.advance();
+        while !matches!(self.buf.current().kind, Kind::LF | Kind::EOF) {
+            args.push(self.macro_arg());
+            self.buf.advance();
+        }
+        let end = self.buf.index.min(self.buf.toks.len());
+
+        let def = &self.macro_defs[name];
+        if depth >= MAX_MACRO_DEPTH {
+            self.recursion_error(&call);
+            return;
+        }
+        if args.len() < def.params.len() {
+            self.err.error(&call, ErrorKind::NotEnoughOperands);
+            return;
+        }
+        if let Some(extra) = args.get(def.params.len()) {
+            self.err.error(&extra[0].0, ErrorKind::ToManyOperands);
+            return;
+        }
+
+        let id = self.expansions.len();
+        let mut expanded = Vec::new();
+        for tok in def.body.iter() {
+            match def.params.iter().position(|param| *param == tok.str) {
+                Some(i) if tok.kind == Kind::Name => expanded.extend(args[i].iter().cloned()),
+                _ => expanded.push((tok.clone(), Some(id))),
+            }
+        }
+        let locals = def.locals.clone();
+        if self.over_budget(expanded.len(), &call) {
+            return;
+        }
+        self.expansions.push(Expansion {call, parent, depth, locals, file: None});
+        self.buf.splice(start..end, expanded);
+        self.buf.index = start;
+    }
+
+    // one macro argument, keeping chars, strings and lists together
+    fn macro_arg(&mut self) -> Vec<(UToken<'a>, Option<usize>)> {
+        let first = self.buf.index;
+        let close = match self.buf.current().kind {
+            Kind::Char => Some(Kind::Char),
+            Kind::String => Some(Kind::String),
+            Kind::LSquare => Some(Kind::RSquare),
+            _ => None,
+        };
+        if let Some(close) = close {
+            while let Some(kind) = self.buf.peek().map(|tok| tok.kind) {
+                if matches!(kind, Kind::LF | Kind::EOF) {
+                    break;
+                }
+                self.buf.bump();
+                if kind == close {
+                    break;
+                }
+            }
+        }
+        (first..=self.buf.index).map(|i| (self.buf.toks[i].clone(), self.buf.origin[i])).collect()
+    }
+
     fn get_reg(&mut self) -> Operand {
         let (ast, op) = self.get_ast_op();
         match ast {
@@ -307,7 +664,7 @@ impl <'a> Parser<'a> {
     fn get_mem(&mut self) -> Operand {
         let (ast, op) = self.get_ast_op();
         match ast {
-            AstOp::Reg(_) | AstOp::Mem(_) | AstOp::Unknown => {},
+            AstOp::Reg(_) | AstOp::Mem(_) | AstOp::Label(_) | AstOp::Unknown => {},
             actual => {
                 self.err.warn(self.buf.cur(), ErrorKind::InvalidOperandType{
                     expected: "memory address", actual
@@ -361,8 +718,17 @@ impl <'a> Parser<'a> {
     }
     fn get_ast_op(&mut self) -> (AstOp, Operand){
         self.buf.advance();
+        let ast = self.current_ast_op();
+        let op = self.trans_op(&ast);
+        (ast, op)
+    }
+
+    fn current_ast_op(&mut self) -> AstOp {
+        if !self.expand_defines() {
+            return AstOp::Unknown;
+        }
         let current = self.buf.current();
-        let ast = match current.kind {
+        match current.kind {
             Kind::Reg(v) => AstOp::Reg(v),
             Kind::Int(v) => AstOp::Int(v as u64),
             Kind::Memory(m) => AstOp::Mem(m),
@@ -422,101 +788,83 @@ impl <'a> Parser<'a> {
             }
             Kind::Macro => {
                 match self.buf.current().str.to_lowercase().as_str() {
-                    "@max" => AstOp::Int(u64::MAX),
-                    "@msb" => AstOp::Int(1 << 63),
-                    "@smax" => AstOp::Int(i64::MAX as u64),
+                    "@max" => AstOp::Int(self.ast.headers.max()),
+                    "@msb" => AstOp::Int(self.ast.headers.msb()),
+                    "@smax" => AstOp::Int(self.ast.headers.smax()),
                     _ => AstOp::Unknown
                 }
             }
-            Kind::Name => {
-                self.get_ast_op_from_token(self.macros[current.str].clone()).0
+            Kind::Name if !self.macros.contains_key(current.str) => {
+                let suggestion = closest(current.str, self.macros.keys().copied());
+                self.err.error(&current, ErrorKind::UndefinedMacro{name: current.str, suggestion});
+                AstOp::Unknown
             }
             _ => {
                 self.err.error(&self.buf.current(), ErrorKind::InvalidOperand);
                 AstOp::Unknown
             }
-        };
-        let op = self.trans_op(&ast);
-        (ast, op)
+        }
     }
 
-    fn get_ast_op_from_token(&mut self, current: UToken<'a>) -> (AstOp, Operand) {
-        let ast = match current.kind {
-            Kind::Reg(v) => AstOp::Reg(v),
-            Kind::Int(v) => AstOp::Int(v as u64),
-            Kind::Memory(m) => AstOp::Mem(m),
-            Kind::PortNum(v) => AstOp::Port(v),
-            Kind::Port => {
-                match IOPort::from_str(&current.str[1..].to_uppercase()) {
-                    Ok(port) => {AstOp::Port(port as u64)},
-                    Err(_err) => {
-                        self.err.error(&self.buf.current(), ErrorKind::UnknownPort);
-                        AstOp::Port(0)
-                    }
-                }
-            }
-            Kind::Label  => AstOp::Label(current.str[1..].to_owned()),
-            Kind::Char => {
-                match self.buf.next().kind {
-                    Kind::Text => {
-                        let a = self.buf.current();
-                        if !matches!(self.buf.next().kind, Kind::Char) {
-                            self.err.error(&self.buf.current(), ErrorKind::EOFBeforeEndOfString);
-                        }
-                        AstOp::Char(a.str.chars().next().unwrap())
-                    }
-                    Kind::Escape(c) => {
-                        if !matches!(self.buf.next().kind, Kind::Char) {
-                            self.err.error(&self.buf.current(), ErrorKind::EOFBeforeEndOfString);
-                        }
-                        AstOp::Char(c)
-                    }
-                    _ => {
-                        self.err.error(&self.buf.current(), ErrorKind::EOFBeforeEndOfString);
-                        AstOp::Char('\x00')
-                    },
-                }
+    fn get_dw(&mut self) {
+        self.buf.advance();
+        if !self.expand_defines() {
+            return;
+        }
+        if self.buf.current().kind != Kind::LSquare {
+            self.dw_value();
+            return;
+        }
+        let start = self.buf.current();
+        loop {
+            self.buf.advance();
+            match self.buf.current().kind {
+                Kind::RSquare => break,
+                Kind::LF if self.next_line_is_code() => {
+                    // leave the line feed for the main loop
+                    self.err.error(&start, ErrorKind::DWNoEnding);
+                    self.buf.unget();
+                    break;
+                },
+                Kind::LF => if self.buf.expansion().is_none() {self.at_line += 1},
+                Kind::EOF => {
+                    self.err.error(&start, ErrorKind::DWNoEnding);
+                    break;
+                },
+                _ => self.dw_value(),
             }
-            Kind::String => {
-                let mut text = String::new();
-                while self.buf.has_next() {match self.buf.next().kind {
-                    Kind::String => break,
-                    Kind::Text => text += self.buf.cur().str,
-                    Kind::Escape(c) => text.push(c),
-                    _ => {
-                        self.err.error(&self.buf.current(), ErrorKind::EOFBeforeEndOfString);
-                        break;
-                    }
-                }}
-
+        }
+    }
 
-                AstOp::String(text)
-            }
-            Kind::Relative(v) => {
-                AstOp::JumpLocation((self.ast.instructions.len() as i64 + v) as u64)
-            }
-            Kind::EOF | Kind::LF => {
-                self.err.error(&self.buf.current(), ErrorKind::NotEnoughOperands);
-                AstOp::Unknown
-            }
-            Kind::Macro => {
-                match self.buf.current().str.to_lowercase().as_str() {
-                    "@max" => AstOp::Int(u64::MAX),
-                    "@msb" => AstOp::Int(1 << 63),
-                    "@smax" => AstOp::Int(i64::MAX as u64),
-                    _ => AstOp::Unknown
-                }
+    fn next_line_is_code(&self) -> bool {
+        for tok in self.buf.toks[self.buf.index+1..].iter() {
+            match tok.kind {
+                Kind::White | Kind::Comment | Kind::LF => {},
+                Kind::Name => return !self.macros.contains_key(tok.str),
+                Kind::EOF => return true,
+                _ => return false,
             }
-            Kind::Name => {
-                self.get_ast_op_from_token(self.macros[current.str].clone()).0
-            }
-            _ => {
-                self.err.error(&self.buf.current(), ErrorKind::InvalidOperand);
-                AstOp::Unknown
-            }
-        };
-        let op = self.trans_op(&ast);
-        (ast, op)
+        }
+        true
+    }
+
+    fn dw_value(&mut self) {
+        let current = self.buf.current();
+        match self.current_ast_op() {
+            AstOp::Unknown => {},
+            AstOp::Int(v) | AstOp::Mem(v) | AstOp::Port(v) | AstOp::JumpLocation(v) => self.ast.memory.push(v),
+            AstOp::Char(c) => self.ast.memory.push(c as u64),
+            AstOp::String(text) => self.ast.memory.extend(text.chars().map(|c| c as u64)),
+            AstOp::Label(_) => {
+                let value = label_tok_to_data(&current, self);
+                self.ast.memory.push(value);
+            },
+            actual @ AstOp::Reg(_) => {
+                self.err.error(&current, ErrorKind::InvalidOperandType{
+                    expected: "data", actual
+                });
+            },
+        }
     }
 
     fn assert_done(&mut self) {
@@ -536,6 +884,7 @@ impl <'a> Parser<'a> {
 #[derive(Debug, PartialEq, Clone)]
 pub struct UndefinedLabel {
     references: Vec<usize>,
+    data_references: Vec<usize>,
     referenced_tokens: Vec<usize>,
 }
 
@@ -545,26 +894,75 @@ pub enum Label {
     Defined(usize),
 }
 
+// closest name by edit distance, if it is close enough to be a typo
+fn closest<'a>(name: &str, names: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    fn distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, ca) in a.chars().enumerate() {
+            let mut prev = row[0];
+            row[0] = i + 1;
+            for (j, cb) in b.iter().enumerate() {
+                let cost = if ca == *cb {prev} else {prev + 1};
+                prev = row[j + 1];
+                row[j + 1] = cost.min(row[j] + 1).min(prev + 1);
+            }
+        }
+        row[b.len()]
+    }
+    let max = (name.chars().count() / 3).max(1);
+    names.map(|other| (distance(name, other), other))
+        .filter(|(d, _)| *d <= max)
+        .min()
+        .map(|(_, other)| other)
+}
+
 fn label_tok_to_operand<'a>(tok: &UToken<'a>, p: &mut Parser) -> Operand {
     if (*tok).kind != Kind::Label {return Operand::Imm(0);}
 
-    match p.ast.labels.get(tok.str) {
+    let name = p.label_key(tok.str, p.buf.index);
+    match p.ast.labels.get(&name) {
         Some(Label::Undefined(v)) => {
             let mut a = v.clone();
             a.references         .push(p.ast.instructions.len());
             a.referenced_tokens  .push(p.buf.index);
-            p.ast.labels.insert((*tok).str.to_string(), Label::Undefined(a));
-            Operand::Label(tok.str.to_string())
+            p.ast.labels.insert(name.clone(), Label::Undefined(a));
+            Operand::Label(name)
         },
         Some(Label::Defined(v)) => Operand::Imm(*v as u64),
         None => {
-            p.ast.labels.insert((*tok).str.to_string(), Label::Undefined(
+            p.ast.labels.insert(name.clone(), Label::Undefined(
                 UndefinedLabel{
                     references: vec![p.ast.instructions.len()],
+                    data_references: Vec::new(),
                     referenced_tokens: vec![p.buf.index]
                 }
             ));
-            Operand::Label(tok.str.to_string())
+            Operand::Label(name)
+        }
+    }
+}
+
+fn label_tok_to_data(tok: &UToken, p: &mut Parser) -> u64 {
+    let name = p.label_key(tok.str, p.buf.index);
+    match p.ast.labels.get(&name) {
+        Some(Label::Undefined(v)) => {
+            let mut a = v.clone();
+            a.data_references    .push(p.ast.memory.len());
+            a.referenced_tokens  .push(p.buf.index);
+            p.ast.labels.insert(name, Label::Undefined(a));
+            0
+        },
+        Some(Label::Defined(v)) => *v as u64,
+        None => {
+            p.ast.labels.insert(name, Label::Undefined(
+                UndefinedLabel{
+                    references: Vec::new(),
+                    data_references: vec![p.ast.memory.len()],
+                    referenced_tokens: vec![p.buf.index]
+                }
+            ));
+            0
         }
     }
 }
@@ -579,19 +977,23 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn new(src: Rc<str>) -> Self {
-        Self { headers: Headers::new(), instructions: Vec::new(), labels: HashMap::new(), memory: Vec::new(), debug: DebugInfo::new(src) }
+    pub fn new(sources: Rc<SourceMap>) -> Self {
+        Self { headers: Headers::new(), instructions: Vec::new(), labels: HashMap::new(), memory: Vec::new(), debug: DebugInfo::new(sources) }
     }
 }
 
 #[derive(Debug)]
 pub struct DebugInfo {
-    pub src: Rc<str>,
-    pub pc_to_line_start: Vec<usize>
+    pub sources: Rc<SourceMap>,
+    // line in the main file, so includes and macros show where they were used
+    pub pc_to_line_start: Vec<usize>,
+    pub pc_to_span: Vec<Range<usize>>,
+    // labels in front of instructions, in pc order
+    pub code_labels: Vec<(usize, String)>,
 }
 impl DebugInfo {
-    pub fn new(src: Rc<str>) -> Self {
-        Self {src, pc_to_line_start: Vec::new()}
+    pub fn new(sources: Rc<SourceMap>) -> Self {
+        Self {sources, pc_to_line_start: Vec::new(), pc_to_span: Vec::new(), code_labels: Vec::new()}
     }
 }
 
@@ -615,6 +1017,18 @@ pub enum Operand {
     Label(String),
 }
 
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Imm(v) => write!(f, "{}", v),
+            Operand::Reg(v) if *v == super::emulator::PC => write!(f, "PC"),
+            Operand::Reg(v) if *v == super::emulator::SP => write!(f, "SP"),
+            Operand::Reg(v) => write!(f, "r{}", v),
+            Operand::Label(name) => write!(f, "{}", name),
+        }
+    }
+}
+
 impl Operand {
     pub fn transform_label(self, label: &str, pc: usize) -> Self {
         if matches!(self, Self::Label(ref l) if l == label) {
@@ -625,9 +1039,42 @@ impl Operand {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitsCmp {
+    Eq, GE, LE,
+}
+
+impl BitsCmp {
+    pub fn accepts(&self, value: u64, bits: u64) -> bool {
+        match self {
+            BitsCmp::Eq => bits == value,
+            BitsCmp::GE => bits >= value,
+            BitsCmp::LE => bits <= value,
+        }
+    }
+    // the width we run at when the host doesnt ask for one
+    pub fn pick(&self, value: u64) -> u64 {
+        match self {
+            BitsCmp::LE => value.min(64),
+            _ => value,
+        }
+    }
+}
+
+impl std::fmt::Display for BitsCmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BitsCmp::Eq => write!(f, "=="),
+            BitsCmp::GE => write!(f, ">="),
+            BitsCmp::LE => write!(f, "<="),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Headers {
     pub bits: u64,
+    pub bits_constraint: Option<(BitsCmp, u64)>,
     pub minheap: u64,
     pub minstack: u64,
     pub minreg: u64
@@ -635,11 +1082,22 @@ pub struct Headers {
 
 impl Headers {
     pub fn new() -> Self {
-        Headers { bits: 8, minheap: 16, minstack: 16, minreg: 8 } // replace all r0 with 0
+        // without a BITS header programs keep running on full 64 bit words like they always did
+        Headers { bits: 64, bits_constraint: None, minheap: 16, minstack: 16, minreg: 8 } // replace all r0 with 0
+    }
+
+    pub fn max(&self) -> u64 {
+        super::emulator::word_mask(self.bits)
+    }
+    pub fn msb(&self) -> u64 {
+        self.max() ^ (self.max() >> 1)
+    }
+    pub fn smax(&self) -> u64 {
+        self.max() >> 1
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, strum_macros::IntoStaticStr)]
 pub enum Inst {
     ADD(Operand, Operand, Operand),
     RSH(Operand, Operand),
@@ -711,3 +1169,67 @@ pub enum Inst {
     CAL(Operand),
     RET,
 }
+
+impl Inst {
+    pub fn operands(&self) -> Vec<&Operand> {
+        use Inst::*;
+        match self {
+            HLT | NOP | RET => vec![],
+            PSH(a) | POP(a) | JMP(a) | CAL(a) => vec![a],
+            RSH(a, b) | LOD(a, b) | STR(a, b) | MOV(a, b) | INC(a, b) | DEC(a, b) | OUT(a, b) | IN(a, b) |
+            LSH(a, b) | NEG(a, b) | NOT(a, b) | CPY(a, b) | ABS(a, b) | BRZ(a, b) | BNZ(a, b) |
+            BOD(a, b) | BEV(a, b) | BRN(a, b) | BRP(a, b) | SRS(a, b) => vec![a, b],
+            ADD(a, b, c) | BGE(a, b, c) | NOR(a, b, c) | SUB(a, b, c) | AND(a, b, c) | OR(a, b, c) |
+            NAND(a, b, c) | MLT(a, b, c) | DIV(a, b, c) | MOD(a, b, c) | LLOD(a, b, c) | LSTR(a, b, c) |
+            SDIV(a, b, c) | SETE(a, b, c) | SETNE(a, b, c) | SETG(a, b, c) | SETGE(a, b, c) | SETL(a, b, c) |
+            SETLE(a, b, c) | XOR(a, b, c) | XNOR(a, b, c) | BNE(a, b, c) | BRE(a, b, c) | SSETG(a, b, c) |
+            SSETGE(a, b, c) | SSETL(a, b, c) | SSETLE(a, b, c) | BRL(a, b, c) | BRG(a, b, c) | BLE(a, b, c) |
+            SETC(a, b, c) | SETNC(a, b, c) | BNC(a, b, c) | BRC(a, b, c) | SBRL(a, b, c) | SBRG(a, b, c) |
+            SBLE(a, b, c) | SBGE(a, b, c) | BSR(a, b, c) | BSL(a, b, c) | BSS(a, b, c) => vec![a, b, c],
+        }
+    }
+    pub fn class(&self) -> InstClass {
+        use Inst::*;
+        match self {
+            ADD(..) | SUB(..) | INC(..) | DEC(..) | NEG(..) | MLT(..) | DIV(..) | MOD(..) | ABS(..) | SDIV(..) => InstClass::Arithmetic,
+            RSH(..) | LSH(..) | SRS(..) | BSR(..) | BSL(..) | BSS(..) | NOR(..) | AND(..) | OR(..) | NOT(..) |
+            NAND(..) | XOR(..) | XNOR(..) => InstClass::Bitwise,
+            SETE(..) | SETNE(..) | SETG(..) | SETGE(..) | SETL(..) | SETLE(..) | SETC(..) | SETNC(..) |
+            SSETG(..) | SSETGE(..) | SSETL(..) | SSETLE(..) => InstClass::Compare,
+            JMP(..) | BGE(..) | BNE(..) | BRE(..) | BRL(..) | BRG(..) | BLE(..) | BRZ(..) | BNZ(..) | BNC(..) |
+            BRC(..) | SBRL(..) | SBRG(..) | SBLE(..) | SBGE(..) | BOD(..) | BEV(..) | BRN(..) | BRP(..) |
+            CAL(..) | RET => InstClass::Branch,
+            LOD(..) | STR(..) | LLOD(..) | LSTR(..) | CPY(..) => InstClass::Memory,
+            PSH(..) | POP(..) => InstClass::Stack,
+            IN(..) | OUT(..) => InstClass::Io,
+            MOV(..) | NOP | HLT => InstClass::Other,
+        }
+    }
+}
+
+// groups of instructions the tracer can filter on
+#[wasm_bindgen::prelude::wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum_macros::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum InstClass {
+    Arithmetic,
+    Bitwise,
+    Compare,
+    Branch,
+    Memory,
+    Stack,
+    Io,
+    Other,
+}
+
+// disassembly, labels are already resolved so jumps show the target pc
+impl std::fmt::Display for Inst {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name: &'static str = self.into();
+        write!(f, "{}", name)?;
+        for op in self.operands() {
+            write!(f, " {}", op)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::{HashMap, HashSet};
+
+// why the emulator stopped with StepResult::Breakpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakReason {
+    Paused,
+    Breakpoint{pc: usize},
+    MemoryRead{addr: u64},
+    MemoryWrite{addr: u64, value: u64},
+    RegisterChanged{reg: u64, old: u64, new: u64},
+    SteppedOver,
+    SteppedOut,
+}
+
+impl std::fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakReason::Paused => write!(f, "Paused"),
+            BreakReason::Breakpoint { pc } => write!(f, "Breakpoint at pc {}", pc),
+            BreakReason::MemoryRead { addr } => write!(f, "Memory address {} was read", addr),
+            BreakReason::MemoryWrite { addr, value } => write!(f, "Memory address {} was set to {}", addr, value),
+            BreakReason::RegisterChanged { reg, old, new } => write!(f, "r{} changed from {} to {}", reg, old, new),
+            BreakReason::SteppedOver => write!(f, "Stepped over call"),
+            BreakReason::SteppedOut => write!(f, "Stepped out of call"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum StepMode {
+    // stop once the call returns to pc with the stack back where it was
+    Over{pc: usize, sp: i64},
+    // calls made since stepping out started, stop once a RET takes it below 0
+    Out{depth: i64},
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    pcs: HashSet<usize>,
+    lines: HashMap<usize, Vec<usize>>,
+    break_pcs: HashSet<usize>,
+    mem_reads: HashSet<u64>,
+    mem_writes: HashSet<u64>,
+    regs: HashSet<u64>,
+    mode: Option<StepMode>,
+    paused: bool,
+    // the instruction we stopped at runs once before breakpoints count again
+    resuming: bool,
+    active: bool,
+    pending: Option<BreakReason>,
+    pub hit: Option<BreakReason>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn update(&mut self) {
+        self.break_pcs = self.pcs.iter().chain(self.lines.values().flatten()).copied().collect();
+        self.active = !self.break_pcs.is_empty() || self.mode.is_some() || self.paused;
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.pcs.insert(pc);
+        self.update();
+    }
+    pub fn remove_breakpoint(&mut self, pc: usize) {
+        self.pcs.remove(&pc);
+        self.update();
+    }
+    pub fn add_line_breakpoint(&mut self, line: usize, pcs: Vec<usize>) {
+        self.lines.insert(line, pcs);
+        self.update();
+    }
+    pub fn remove_line_breakpoint(&mut self, line: usize) {
+        self.lines.remove(&line);
+        self.update();
+    }
+    pub fn watch_memory(&mut self, addr: u64, read: bool, write: bool) {
+        if read {self.mem_reads.insert(addr);} else {self.mem_reads.remove(&addr);}
+        if write {self.mem_writes.insert(addr);} else {self.mem_writes.remove(&addr);}
+    }
+    pub fn watch_register(&mut self, reg: u64, watch: bool) {
+        if watch {self.regs.insert(reg);} else {self.regs.remove(&reg);}
+    }
+    pub fn clear(&mut self) {
+        *self = Self {hit: self.hit.take(), resuming: self.resuming, ..Self::default()};
+    }
+
+    // after stepping back, run the instruction we landed on even if it has a breakpoint
+    pub fn rewound(&mut self) {
+        self.hit = None;
+        self.resuming = true;
+    }
+
+    // IN had no input yet and runs again later, whatever stopped before it already did
+    pub fn retry(&mut self) {
+        self.resuming = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.update();
+    }
+    pub fn step_over(&mut self, pc: usize, sp: i64) {
+        self.mode = Some(StepMode::Over{pc, sp});
+        self.resuming = true;
+        self.update();
+    }
+    pub fn step_out(&mut self) {
+        self.mode = Some(StepMode::Out{depth: 0});
+        self.resuming = true;
+        self.update();
+    }
+    // single step, stops before whatever instruction comes next
+    pub fn step(&mut self) {
+        self.paused = true;
+        self.resuming = true;
+        self.update();
+    }
+
+    // checked before every instruction, true means stop before running it
+    #[inline]
+    pub fn before(&mut self, pc: usize, sp: i64) -> bool {
+        if !self.active && self.hit.is_none() {
+            return false;
+        }
+        self.hit = None;
+        if std::mem::take(&mut self.resuming) || !self.active {
+            return false;
+        }
+        let reason = match self.mode {
+            Some(StepMode::Over{pc: target, sp: start}) if pc == target && sp >= start => Some(BreakReason::SteppedOver),
+            Some(StepMode::Out{depth}) if depth < 0 => Some(BreakReason::SteppedOut),
+            _ if self.paused => Some(BreakReason::Paused),
+            _ if self.break_pcs.contains(&pc) => Some(BreakReason::Breakpoint{pc}),
+            _ => None,
+        };
+        match reason {
+            Some(reason) => {
+                self.stop(reason);
+                self.resuming = true;
+                true
+            },
+            None => false,
+        }
+    }
+
+    #[inline]
+    pub fn call(&mut self) {
+        if let Some(StepMode::Out{depth}) = &mut self.mode {
+            *depth += 1;
+        }
+    }
+    #[inline]
+    pub fn ret(&mut self) {
+        if let Some(StepMode::Out{depth}) = &mut self.mode {
+            *depth -= 1;
+        }
+    }
+
+    // any stop ends a step over or out that was still going
+    fn stop(&mut self, reason: BreakReason) {
+        self.mode = None;
+        self.paused = false;
+        self.update();
+        self.hit = Some(reason);
+    }
+
+    #[inline]
+    pub fn watches_memory(&self) -> bool {
+        !self.mem_reads.is_empty() || !self.mem_writes.is_empty()
+    }
+    pub fn mem_read(&mut self, addr: u64) {
+        if self.mem_reads.contains(&addr) {
+            self.pending = Some(BreakReason::MemoryRead{addr});
+        }
+    }
+    pub fn mem_write(&mut self, addr: u64, value: u64) {
+        if self.mem_writes.contains(&addr) {
+            self.pending = Some(BreakReason::MemoryWrite{addr, value});
+        }
+    }
+
+    #[inline]
+    pub fn watches_registers(&self) -> bool {
+        !self.regs.is_empty()
+    }
+    pub fn watched_registers(&self) -> impl Iterator<Item = u64> + '_ {
+        self.regs.iter().copied()
+    }
+    pub fn reg_changed(&mut self, reg: u64, old: u64, new: u64) {
+        self.pending = Some(BreakReason::RegisterChanged{reg, old, new});
+    }
+
+    // checked after every instruction, true means a watchpoint was hit
+    #[inline]
+    pub fn after(&mut self) -> bool {
+        match self.pending.take() {
+            Some(reason) => {
+                self.stop(reason);
+                true
+            },
+            None => false,
+        }
+    }
+}
@@ -1,17 +1,136 @@
+use std::collections::VecDeque;
+
+use super::text::{Decoder, Encoding};
+use super::number;
+use super::IOPort;
+use super::super::emulator::EmulatorErrorKind;
+
 pub struct Console { // console::console::console::console::console::console::console::console::console
-    output: String
+    output: String,
+    input: VecDeque<char>,
+    // bytes cleared from the front of output, so undo can find its place again
+    trimmed: usize,
+    decoder: Decoder,
+    // the rest of a character being read a word at a time, like the later bytes in utf8
+    pending: Option<(Encoding, VecDeque<u64>)>,
+    // bits after the binary point for %FIXED, None is half the word
+    pub fixed_point: Option<u64>,
 }
 impl Console {
     pub fn new() -> Self {
-        Self { output: String::new() }
+        Self { output: String::new(), input: VecDeque::new(), trimmed: 0, decoder: Decoder::default(), pending: None, fixed_point: None }
+    }
+
+    pub fn written(&self) -> usize {
+        self.trimmed + self.output.len()
+    }
+    pub fn unwrite(&mut self, written: usize) {
+        let keep = written.saturating_sub(self.trimmed);
+        if keep < self.output.len() {
+            self.output.truncate(keep);
+        }
+    }
+    pub fn queued_input(&self) -> Vec<char> {
+        self.input.iter().copied().collect()
+    }
+    pub fn input_len(&self) -> usize {
+        self.input.len()
+    }
+    pub fn unread(&mut self, chars: &[char]) {
+        for c in chars.iter().rev() {
+            self.input.push_front(*c);
+        }
+    }
+    pub fn decoder(&self) -> Decoder {
+        self.decoder
+    }
+    pub fn set_decoder(&mut self, decoder: Decoder) {
+        self.decoder = decoder;
+    }
+    pub fn pending(&self) -> Option<(Encoding, VecDeque<u64>)> {
+        self.pending.clone()
+    }
+    pub fn set_pending(&mut self, pending: Option<(Encoding, VecDeque<u64>)>) {
+        self.pending = pending;
+    }
+
+    pub fn push_input(&mut self, text: &str) {
+        self.input.extend(text.chars());
+    }
+
+    // characters the encoding doesnt have are read as ?
+    pub fn intext(&mut self, encoding: Encoding) -> Option<u64> {
+        // reading with another port drops the rest of the character
+        if let Some((pending, mut words)) = self.pending.take().filter(|(pending, _)| *pending == encoding) {
+            let word = words.pop_front();
+            if !words.is_empty() {
+                self.pending = Some((pending, words));
+            }
+            return word;
+        }
+        let c = self.input.pop_front()?;
+        let mut words: VecDeque<u64> = encoding.encode(c).or_else(|| encoding.encode('?')).unwrap_or_default().into();
+        let word = words.pop_front();
+        if !words.is_empty() {
+            self.pending = Some((encoding, words));
+        }
+        word
+    }
+    pub fn innumb(&mut self) -> Result<Option<u64>, EmulatorErrorKind> {
+        self.read_number(IOPort::NUMB, |word| word.parse().ok())
+    }
+    pub fn inhex(&mut self) -> Result<Option<u64>, EmulatorErrorKind> {
+        self.read_number(IOPort::HEX, |word| {
+            let word = word.trim_start_matches("0x").trim_start_matches("0X");
+            u64::from_str_radix(word, 16).ok()
+        })
+    }
+    pub fn inint(&mut self) -> Result<Option<u64>, EmulatorErrorKind> {
+        self.read_number(IOPort::INT, |word| word.parse::<i64>().ok().map(|v| v as u64))
+    }
+    pub fn inuint(&mut self) -> Result<Option<u64>, EmulatorErrorKind> {
+        self.read_number(IOPort::UINT, |word| word.parse().ok())
+    }
+    pub fn inbin(&mut self) -> Result<Option<u64>, EmulatorErrorKind> {
+        self.read_number(IOPort::BIN, |word| {
+            let word = word.trim_start_matches("0b").trim_start_matches("0B");
+            u64::from_str_radix(word, 2).ok()
+        })
+    }
+    pub fn infloat(&mut self, bits: u64) -> Result<Option<u64>, EmulatorErrorKind> {
+        if bits < number::MIN_FLOAT_BITS {
+            return Err(EmulatorErrorKind::UnsupportedBits { port: IOPort::FLOAT as u64, bits });
+        }
+        self.read_number(IOPort::FLOAT, |word| number::parse_float(word, bits))
+    }
+    pub fn infixed(&mut self, bits: u64) -> Result<Option<u64>, EmulatorErrorKind> {
+        let point = self.point(bits);
+        self.read_number(IOPort::FIXED, |word| number::parse_fixed(word, bits, point))
+    }
+    fn point(&self, bits: u64) -> u64 {
+        self.fixed_point.unwrap_or(bits / 2).min(bits)
+    }
+
+    // numbers are separated by whitespace, None means we need more input
+    // a word that isnt a number is left queued so the host can show what went wrong
+    fn read_number(&mut self, port: IOPort, parse: impl FnOnce(&str) -> Option<u64>) -> Result<Option<u64>, EmulatorErrorKind> {
+        while self.input.front().is_some_and(|c| c.is_whitespace()) {
+            self.input.pop_front();
+        }
+        if self.input.is_empty() {
+            return Ok(None);
+        }
+        let word: String = self.input.iter().take_while(|c| !c.is_whitespace()).collect();
+        let value = parse(&word).ok_or(EmulatorErrorKind::InvalidNumber { port: port as u64 })?;
+        self.input.drain(..word.chars().count());
+        Ok(Some(value))
     }
 
-    pub fn outtext(&mut self, value: u64){
-        if let Some(c) = char::from_u32(value as u32) {
+    pub fn outtext(&mut self, encoding: Encoding, value: u64) -> Result<(), EmulatorErrorKind> {
+        if let Some(c) = self.decoder.decode(encoding, value)? {
             self.output.push(c);
-        } else {
-            // todo: report error
         }
+        Ok(())
     }
     pub fn outnumb(&mut self, value: u64){
         self.output.push_str(&value.to_string());
@@ -19,8 +138,22 @@ impl Console {
     pub fn outhex(&mut self, value: u64){
         self.output.push_str(&format!("{:X}", value));
     }
-    pub fn outint(&mut self, value: u64){
-        self.output.push_str(&(value as i64).to_string())
+    pub fn outint(&mut self, value: i64){
+        self.output.push_str(&value.to_string())
+    }
+    pub fn outuint(&mut self, value: u64) {
+        self.outnumb(value);
+    }
+    pub fn outbin(&mut self, value: u64) {
+        self.output.push_str(&format!("{:b}", value));
+    }
+    pub fn outfloat(&mut self, value: u64, bits: u64) -> Result<(), EmulatorErrorKind> {
+        let text = number::float_to_string(value, bits).ok_or(EmulatorErrorKind::UnsupportedBits { port: IOPort::FLOAT as u64, bits })?;
+        self.output.push_str(&text);
+        Ok(())
+    }
+    pub fn outfixed(&mut self, value: u64, bits: u64) {
+        self.output.push_str(&number::fixed_to_string(value, bits, self.point(bits)));
     }
 
     pub fn get_output(&self) -> &str {
@@ -37,6 +170,7 @@ impl Console {
             new_output = chars.as_str().to_owned();
         }
 
+        self.trimmed += self.output.len() - new_output.len();
         self.output = new_output;
     }
 }
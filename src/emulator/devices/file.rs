@@ -0,0 +1,207 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+
+use super::super::emulator::{EmulatorErrorKind, word_mask};
+
+// where %FILE reads and writes, the cli gets directories on disk and everything else a map in memory
+#[derive(Debug, Clone)]
+pub enum FileSystem {
+    Memory(HashMap<String, Vec<u8>>),
+    // only files inside these (canonical) directories can be touched
+    Disk(Vec<PathBuf>),
+}
+
+impl FileSystem {
+    fn resolve(allowed: &[PathBuf], name: &str) -> Result<PathBuf, EmulatorErrorKind> {
+        let path = Path::new(name);
+        // relative names live in the allowed directories not the cwd, the first one that has the file wins
+        let candidates: Vec<PathBuf> = match path.is_absolute() {
+            true => vec![path.to_path_buf()],
+            false => allowed.iter().map(|dir| dir.join(path)).collect(),
+        };
+        let path = candidates.iter().find(|path| path.symlink_metadata().is_ok())
+            .or_else(|| candidates.iter().find(|path| path.parent().is_some_and(Path::is_dir)))
+            .ok_or(EmulatorErrorKind::FileAccessDenied)?;
+        // file_name is None for .. so it cant be used to climb out
+        let (Some(parent), Some(file)) = (path.parent(), path.file_name()) else {return Err(EmulatorErrorKind::FileAccessDenied)};
+        let Ok(parent) = parent.canonicalize() else {return Err(EmulatorErrorKind::FileAccessDenied)};
+        let full = parent.join(file);
+        // a link can point anywhere, even at a file that doesnt exist yet, so they are never followed
+        if full.symlink_metadata().is_ok_and(|meta| meta.file_type().is_symlink()) {
+            return Err(EmulatorErrorKind::FileAccessDenied);
+        }
+        match allowed.iter().any(|dir| full.starts_with(dir)) {
+            true => Ok(full),
+            false => Err(EmulatorErrorKind::FileAccessDenied),
+        }
+    }
+
+    // a file that doesnt exist yet reads as empty
+    fn load(&self, name: &str) -> Result<Vec<u8>, EmulatorErrorKind> {
+        match self {
+            FileSystem::Memory(files) => Ok(files.get(name).cloned().unwrap_or_default()),
+            FileSystem::Disk(allowed) => match std::fs::read(Self::resolve(allowed, name)?) {
+                Ok(data) => Ok(data),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+                Err(_) => Err(EmulatorErrorKind::FileIo),
+            },
+        }
+    }
+
+    fn save(&mut self, name: &str, data: &[u8]) -> Result<(), EmulatorErrorKind> {
+        match self {
+            FileSystem::Memory(files) => {
+                files.insert(name.to_string(), data.to_vec());
+                Ok(())
+            },
+            FileSystem::Disk(allowed) => std::fs::write(Self::resolve(allowed, name)?, data).map_err(|_| EmulatorErrorKind::FileIo),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OpenFile {
+    name: String,
+    data: Vec<u8>,
+    pos: usize,
+    written: bool,
+    // what was there before the first write emptied the file
+    original: Vec<u8>,
+}
+
+// OUT %FILE sends the name a character at a time and 0 opens it,
+// after that IN and OUT stream bytes and OUT of all ones (-1) closes it again.
+// IN gives all ones at the end of the file, or when nothing is open.
+// with 8 bit words all ones is also a byte, so there IN %FILE_STATUS tells if a byte is left
+// and OUT %FILE_STATUS closes the file.
+// the first write empties the file so output never mixes with what was there,
+// it only reaches the file system once the file is closed
+#[derive(Debug, Clone)]
+pub struct Files {
+    fs: FileSystem,
+    name: Vec<u8>,
+    open: Option<OpenFile>,
+    // the file closed last, until the journal takes it
+    closed: Option<OpenFile>,
+}
+
+// enough to put back whatever one IN or OUT did
+#[derive(Debug, Clone)]
+pub struct FileUndo {
+    name: Vec<u8>,
+    // pos, written, len and the byte at pos
+    open: Option<(usize, bool, usize, Option<u8>)>,
+    closed: Option<Box<OpenFile>>,
+}
+
+impl Default for Files {
+    fn default() -> Self {
+        Self::new(FileSystem::Memory(HashMap::new()))
+    }
+}
+
+impl Files {
+    pub fn new(fs: FileSystem) -> Self {
+        Self { fs, name: Vec::new(), open: None, closed: None }
+    }
+
+    pub fn set_file_system(&mut self, fs: FileSystem) {
+        *self = Self::new(fs);
+    }
+    // only does anything for the in memory file system
+    pub fn insert(&mut self, name: &str, data: Vec<u8>) {
+        if let FileSystem::Memory(files) = &mut self.fs {
+            files.insert(name.to_string(), data);
+        }
+    }
+    pub fn remove(&mut self, name: &str) {
+        if let FileSystem::Memory(files) = &mut self.fs {
+            files.remove(name);
+        }
+    }
+    // includes what has been written to a file that is still open
+    pub fn get(&self, name: &str) -> Option<Vec<u8>> {
+        match (&self.open, &self.fs) {
+            (Some(file), _) if file.name == name && file.written => Some(file.data.clone()),
+            (_, FileSystem::Memory(files)) => files.get(name).cloned(),
+            (_, FileSystem::Disk(_)) => self.fs.load(name).ok(),
+        }
+    }
+
+    pub fn out(&mut self, bits: u64, value: u64) -> Result<(), EmulatorErrorKind> {
+        match &mut self.open {
+            None if value == 0 => {
+                let name = String::from_utf8_lossy(&std::mem::take(&mut self.name)).into_owned();
+                let data = self.fs.load(&name)?;
+                self.open = Some(OpenFile { name, data, pos: 0, written: false, original: Vec::new() });
+            },
+            None => self.name.push(value as u8),
+            Some(_) if bits > 8 && value == word_mask(bits) => self.close()?,
+            Some(file) => {
+                if !file.written {
+                    file.original = std::mem::take(&mut file.data);
+                    file.pos = 0;
+                    file.written = true;
+                }
+                match file.data.get_mut(file.pos) {
+                    Some(byte) => *byte = value as u8,
+                    None => file.data.push(value as u8),
+                }
+                file.pos += 1;
+            },
+        }
+        Ok(())
+    }
+
+    pub fn in_file(&mut self, bits: u64) -> u64 {
+        let Some(file) = &mut self.open else {return word_mask(bits)};
+        match file.data.get(file.pos) {
+            Some(&byte) => {
+                file.pos += 1;
+                byte as u64
+            },
+            None => word_mask(bits),
+        }
+    }
+
+    pub fn remaining(&self) -> bool {
+        self.open.as_ref().is_some_and(|file| file.pos < file.data.len())
+    }
+
+    pub fn close(&mut self) -> Result<(), EmulatorErrorKind> {
+        let Some(file) = self.open.take() else {return Ok(())};
+        let result = match file.written {
+            true => self.fs.save(&file.name, &file.data),
+            false => Ok(()),
+        };
+        self.closed = Some(file);
+        result
+    }
+
+    pub fn undo_point(&self) -> FileUndo {
+        let open = self.open.as_ref().map(|file| (file.pos, file.written, file.data.len(), file.data.get(file.pos).copied()));
+        FileUndo { name: self.name.clone(), open, closed: None }
+    }
+    pub fn undo_done(&mut self, mut undo: FileUndo) -> FileUndo {
+        undo.closed = self.closed.take().map(Box::new);
+        undo
+    }
+    pub fn undo(&mut self, undo: FileUndo) {
+        self.name = undo.name;
+        match (undo.open, &mut self.open) {
+            (None, Some(_)) => self.open = None,
+            (Some(_), None) => self.open = undo.closed.map(|file| *file),
+            (Some((pos, written, len, byte)), Some(file)) => {
+                if !written && file.written {
+                    file.data = std::mem::take(&mut file.original);
+                    file.written = false;
+                }
+                file.data.truncate(len);
+                if let (Some(byte), Some(old)) = (byte, file.data.get_mut(pos)) {
+                    *old = byte;
+                }
+                file.pos = pos;
+            },
+            (None, None) => {},
+        }
+    }
+}
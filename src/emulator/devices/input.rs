@@ -0,0 +1,148 @@
+use std::collections::{HashSet, VecDeque};
+
+// key codes are whatever the host sends, the web uses KeyboardEvent.keyCode
+// so letters and digits are their uppercase ascii
+#[derive(Debug, Clone, Default)]
+pub struct Keyboard {
+    down: HashSet<u64>,
+    // OUT %KEY picks the key IN %KEY reports on
+    pub selected: u64,
+}
+
+impl Keyboard {
+    pub fn key_down(&mut self, key: u64) {
+        self.down.insert(key);
+    }
+    pub fn key_up(&mut self, key: u64) {
+        self.down.remove(&key);
+    }
+    pub fn in_key(&self) -> u64 {
+        self.down.contains(&self.selected) as u64
+    }
+}
+
+// position in screen pixels, the deltas add up until the program reads them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Mouse {
+    pub x: i64,
+    pub y: i64,
+    pub dx: i64,
+    pub dy: i64,
+    pub dwheel: i64,
+    // bit 0 left, bit 1 right, bit 2 middle, like MouseEvent.buttons
+    pub buttons: u64,
+}
+
+impl Mouse {
+    pub fn move_to(&mut self, x: i64, y: i64) {
+        self.dx += x - self.x;
+        self.dy += y - self.y;
+        self.x = x;
+        self.y = y;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Gamepad {
+    pub buttons: u64,
+    // -1 to 1
+    pub axes: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Gamepads {
+    pads: Vec<Option<Gamepad>>,
+    // OUT %GAMEPAD and OUT %AXIS pick what the IN ports report on
+    pub selected: usize,
+    pub axis: usize,
+}
+
+impl Gamepads {
+    pub fn set(&mut self, index: usize, pad: Option<Gamepad>) {
+        if index >= self.pads.len() {
+            self.pads.resize(index + 1, None);
+        }
+        self.pads[index] = pad;
+    }
+    pub fn connected(&self) -> u64 {
+        self.pads.iter().filter(|pad| pad.is_some()).count() as u64
+    }
+    pub fn in_buttons(&self) -> u64 {
+        self.pads.get(self.selected).and_then(Option::as_ref).map_or(0, |pad| pad.buttons)
+    }
+    // signed and scaled so a full tilt is the largest signed word
+    pub fn in_axis(&self, bits: u64) -> u64 {
+        let value = self.pads.get(self.selected).and_then(Option::as_ref).and_then(|pad| pad.axes.get(self.axis)).copied().unwrap_or(0.0);
+        let smax = (super::super::emulator::word_mask(bits) >> 1) as f64;
+        (value.clamp(-1.0, 1.0) * smax).round() as i64 as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum InputEvent {
+    KeyDown(u64),
+    KeyUp(u64),
+    MouseMove(i64, i64),
+    MouseButtons(u64),
+    Wheel(i64),
+    Gamepad(usize, Option<Gamepad>),
+}
+
+// input for running without a host, each event happens once the program has run that many instructions
+//   # comment
+//   0    key_down 65
+//   120  key_up 65
+//   10   mouse_move 3 4
+//   10   mouse_buttons 1
+//   10   wheel -1
+//   30   gamepad 0 5 0.5 -1     (index, buttons, then the axes)
+//   90   gamepad_off 0
+#[derive(Debug, Clone, Default)]
+pub struct InputScript {
+    events: VecDeque<(u64, InputEvent)>,
+}
+
+impl InputScript {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut events = Vec::new();
+        for (i, line) in src.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| format!("input script line {}: {}", i + 1, msg);
+            let int = |i: usize| words.get(i).and_then(|w| w.parse::<i64>().ok()).ok_or_else(|| err("expected a number"));
+            let step = words[0].parse::<u64>().map_err(|_| err("expected the step the event happens at"))?;
+            let event = match words.get(1).copied().unwrap_or("") {
+                "key_down" => InputEvent::KeyDown(int(2)? as u64),
+                "key_up" => InputEvent::KeyUp(int(2)? as u64),
+                "mouse_move" => InputEvent::MouseMove(int(2)?, int(3)?),
+                "mouse_buttons" => InputEvent::MouseButtons(int(2)? as u64),
+                "wheel" => InputEvent::Wheel(int(2)?),
+                "gamepad" => {
+                    let axes = words[4.min(words.len())..].iter().map(|w| w.parse::<f64>().map_err(|_| err("expected an axis from -1 to 1")));
+                    InputEvent::Gamepad(int(2)? as usize, Some(Gamepad {buttons: int(3)? as u64, axes: axes.collect::<Result<_, _>>()?}))
+                },
+                "gamepad_off" => InputEvent::Gamepad(int(2)? as usize, None),
+                other => return Err(err(&format!("unknown event {}", other))),
+            };
+            events.push((step, event));
+        }
+        // stable so events on the same step keep their order
+        events.sort_by_key(|(step, _)| *step);
+        Ok(Self {events: events.into()})
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    // events that are due by step
+    pub fn due(&mut self, step: u64) -> impl Iterator<Item = InputEvent> + '_ {
+        std::iter::from_fn(move || match self.events.front() {
+            Some((at, _)) if *at <= step => self.events.pop_front().map(|(_, event)| event),
+            _ => None,
+        })
+    }
+}
@@ -1,8 +1,16 @@
 pub mod console;
 pub mod screen;
+pub mod input;
+pub mod storage;
+pub mod file;
+pub mod sound;
+pub mod rng;
+pub mod text;
+pub mod number;
 use console::Console;
 use self::screen::Screen;
 use super::super::*;
+use super::emulator::EmulatorErrorKind;
 
 use strum_macros::EnumString;
 use num_derive::FromPrimitive;    
@@ -33,6 +41,8 @@ pub enum IOPort {
     MOUSE_DWHEEL,
     MOUSE_BUTTONS,
     FILE,
+    // 1 while the open file has bytes left, OUT closes it
+    FILE_STATUS,
 }
 
 
@@ -43,46 +53,262 @@ pub trait Device {
 pub struct DeviceHost {
     pub console: console::Console,
     pub screen: screen::Screen,
+    pub keyboard: input::Keyboard,
+    pub mouse: input::Mouse,
+    pub gamepads: input::Gamepads,
+    pub script: Option<input::InputScript>,
+    pub storage: storage::Storage,
+    pub files: file::Files,
+    pub sound: sound::Sound,
+    pub rng: rng::Rng,
+    // the emulator's clock, kept up to date for the devices that need the time
+    pub cycles: u64,
+    pub bits: u64,
 }//rip
 // we could take a break from ports and add other bits than 64
-use std::fmt::{Formatter, Result, Debug};
+use std::fmt::{Formatter, Debug};
 impl Debug for DeviceHost {
-    fn fmt(&self, fmt: &mut Formatter<'_>) -> Result {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
         "DeviceHost lol (clearly this is the best formatting)".fmt(fmt)
     }
 }
 
+// ports that read or write the console
+fn uses_console(port: IOPort) -> bool {
+    matches!(port, IOPort::NUMB | IOPort::INT | IOPort::UINT | IOPort::BIN | IOPort::HEX | IOPort::FLOAT | IOPort::FIXED)
+        || text::Encoding::from_port(port).is_some()
+}
+
+// what an IN or OUT changed, so the journal can put it back
+#[derive(Debug, Clone)]
+pub enum DeviceUndo {
+    Nothing,
+    Output{written: usize, decoder: text::Decoder},
+    Input{taken: Vec<char>, pending: Option<(text::Encoding, std::collections::VecDeque<u64>)>},
+    Screen{x: usize, y: usize, pixel: Option<(usize, u32)>},
+    // %BUFFER can touch every pixel so the whole screen is kept
+    ScreenState(Box<Screen>),
+    // reading a mouse delta resets it
+    Mouse(input::Mouse),
+    KeySelect(u64),
+    GamepadSelect(usize, usize),
+    StorageAddr(u64, u64),
+    StorageWrite{index: usize, old: u64, len: usize},
+    File(file::FileUndo),
+    Sound(u64, sound::Instrument, usize),
+    Rng(Option<u64>),
+    FixedPoint(Option<u64>),
+}
+
 impl DeviceHost {
-    pub fn in_port(&mut self, _port: u64) -> u64 {
-        let Some(port) = FromPrimitive::from_u64(_port) else {return 0;};
-        match port {
-            IOPort::RNG => crate::rand(),
-            _ => {todo!("unsupported port {:?}", port)}
+    // taken before the port is used
+    pub fn undo_point(&self, port: u64, input: bool) -> DeviceUndo {
+        match (FromPrimitive::from_u64(port), input) {
+            (Some(port), true) if uses_console(port) => DeviceUndo::Input{taken: self.console.queued_input(), pending: self.console.pending()},
+            (Some(port), false) if uses_console(port) => DeviceUndo::Output{written: self.console.written(), decoder: self.console.decoder()},
+            (Some(IOPort::X | IOPort::Y | IOPort::COLOR), false) => {
+                let (x, y, pixel) = self.screen.undo_point();
+                DeviceUndo::Screen{x, y, pixel}
+            },
+            (Some(IOPort::BUFFER | IOPort::G_SPECIAL), false) => DeviceUndo::ScreenState(Box::new(self.screen.clone())),
+            (Some(IOPort::MOUSE_DX | IOPort::MOUSE_DY | IOPort::MOUSE_DWHEEL), true) => DeviceUndo::Mouse(self.mouse),
+            (Some(IOPort::KEY), false) => DeviceUndo::KeySelect(self.keyboard.selected),
+            (Some(IOPort::GAMEPAD | IOPort::AXIS), false) => DeviceUndo::GamepadSelect(self.gamepads.selected, self.gamepads.axis),
+            (Some(IOPort::ADDR | IOPort::PAGE), false) => DeviceUndo::StorageAddr(self.storage.addr, self.storage.page),
+            (Some(IOPort::BUS), false) => match self.storage.undo_point(self.bits) {
+                Some((index, old, len)) => DeviceUndo::StorageWrite{index, old, len},
+                None => DeviceUndo::Nothing,
+            },
+            (Some(IOPort::FILE | IOPort::FILE_STATUS), _) => DeviceUndo::File(self.files.undo_point()),
+            (Some(IOPort::RNG), _) => DeviceUndo::Rng(self.rng.state()),
+            (Some(IOPort::N_SPECIAL), false) => DeviceUndo::FixedPoint(self.console.fixed_point),
+            (Some(IOPort::NOTE | IOPort::INSTR), false) => {
+                let (note, instrument, len) = self.sound.undo_point();
+                DeviceUndo::Sound(note, instrument, len)
+            },
+            _ => DeviceUndo::Nothing,
+        }
+    }
+    // taken after the port was used, keeps only the input that was actually read
+    pub fn undo_done(&mut self, undo: DeviceUndo) -> DeviceUndo {
+        match undo {
+            DeviceUndo::Input{mut taken, pending} => {
+                taken.truncate(taken.len() - self.console.input_len().min(taken.len()));
+                DeviceUndo::Input{taken, pending}
+            },
+            DeviceUndo::File(undo) => DeviceUndo::File(self.files.undo_done(undo)),
+            undo => undo,
         }
     }
+    pub fn undo(&mut self, undo: DeviceUndo) {
+        match undo {
+            DeviceUndo::Nothing => {},
+            DeviceUndo::Output{written, decoder} => {
+                self.console.unwrite(written);
+                self.console.set_decoder(decoder);
+            },
+            DeviceUndo::Input{taken, pending} => {
+                self.console.unread(&taken);
+                self.console.set_pending(pending);
+            },
+            DeviceUndo::Screen{x, y, pixel} => self.screen.undo(x, y, pixel),
+            DeviceUndo::ScreenState(screen) => self.screen = *screen,
+            DeviceUndo::Mouse(mouse) => self.mouse = mouse,
+            DeviceUndo::KeySelect(key) => self.keyboard.selected = key,
+            DeviceUndo::GamepadSelect(pad, axis) => {
+                self.gamepads.selected = pad;
+                self.gamepads.axis = axis;
+            },
+            DeviceUndo::StorageAddr(addr, page) => {
+                self.storage.addr = addr;
+                self.storage.page = page;
+            },
+            DeviceUndo::StorageWrite{index, old, len} => self.storage.undo(index, old, len),
+            DeviceUndo::File(undo) => self.files.undo(undo),
+            DeviceUndo::Sound(note, instrument, len) => self.sound.undo(note, instrument, len),
+            DeviceUndo::Rng(state) => self.rng.restore(state),
+            DeviceUndo::FixedPoint(point) => self.console.fixed_point = point,
+        }
+    }
+
+    // Ok(None) means the port is waiting for the host to push input
+    pub fn in_port(&mut self, _port: u64) -> Result<Option<u64>, EmulatorErrorKind> {
+        let Some(port) = FromPrimitive::from_u64(_port) else {return Err(EmulatorErrorKind::UnsupportedPort { port: _port });};
+        if let Some(encoding) = text::Encoding::from_port(port) {
+            return Ok(self.console.intext(encoding));
+        }
+        Ok(match port {
+            IOPort::NUMB => self.console.innumb()?,
+            IOPort::INT => self.console.inint()?,
+            IOPort::HEX => self.console.inhex()?,
+            IOPort::UINT => self.console.inuint()?,
+            IOPort::BIN => self.console.inbin()?,
+            IOPort::FLOAT => self.console.infloat(self.bits)?,
+            IOPort::FIXED => self.console.infixed(self.bits)?,
+            // where the binary point of %FIXED is
+            IOPort::N_SPECIAL => Some(self.console.fixed_point.unwrap_or(self.bits / 2).min(self.bits)),
+            IOPort::RNG => Some(self.rng.in_rng(self.bits)),
+            IOPort::X => Some(self.screen.width() as u64),
+            IOPort::Y => Some(self.screen.height() as u64),
+            IOPort::COLOR => Some(self.screen.in_color()),
+            IOPort::G_SPECIAL => Some(self.screen.mode() as u64),
+            IOPort::KEY => Some(self.keyboard.in_key()),
+            IOPort::MOUSE_X => Some(self.mouse.x as u64),
+            IOPort::MOUSE_Y => Some(self.mouse.y as u64),
+            IOPort::MOUSE_DX => Some(std::mem::take(&mut self.mouse.dx) as u64),
+            IOPort::MOUSE_DY => Some(std::mem::take(&mut self.mouse.dy) as u64),
+            IOPort::MOUSE_DWHEEL => Some(std::mem::take(&mut self.mouse.dwheel) as u64),
+            IOPort::MOUSE_BUTTONS => Some(self.mouse.buttons),
+            IOPort::GAMEPAD => Some(self.gamepads.in_buttons()),
+            IOPort::AXIS => Some(self.gamepads.in_axis(self.bits)),
+            IOPort::GAMEPAD_INFO => Some(self.gamepads.connected()),
+            IOPort::ADDR => Some(self.storage.addr),
+            IOPort::PAGE => Some(self.storage.page),
+            IOPort::BUS => Some(self.storage.in_bus(self.bits)?),
+            // how many pages there are
+            IOPort::S_SPECIAL => Some(self.storage.pages(self.bits)),
+            IOPort::FILE => Some(self.files.in_file(self.bits)),
+            IOPort::FILE_STATUS => Some(self.files.remaining() as u64),
+            IOPort::NOTE => Some(self.sound.note),
+            IOPort::INSTR => Some(self.sound.instrument as u64),
+            _ => return Err(EmulatorErrorKind::UnsupportedPort { port: _port }),
+        })
+    }
 
-    pub fn out(&mut self, _port: u64, value: u64) {
-        let Some(port) = FromPrimitive::from_u64(_port) else {return;};
+    pub fn out(&mut self, _port: u64, value: u64) -> Result<(), EmulatorErrorKind> {
+        let Some(port) = FromPrimitive::from_u64(_port) else {return Err(EmulatorErrorKind::UnsupportedPort { port: _port });};
+        if let Some(encoding) = text::Encoding::from_port(port) {
+            return self.console.outtext(encoding, value);
+        }
         match port {
-            IOPort::TEXT => self.console.outtext(value),
             IOPort::NUMB => self.console.outnumb(value),
-            IOPort::INT => self.console.outint(value),
+            IOPort::INT => self.console.outint(super::emulator::sign_extend(value, self.bits)),
             IOPort::HEX => self.console.outhex(value),
+            IOPort::UINT => self.console.outuint(value),
+            IOPort::BIN => self.console.outbin(value),
+            IOPort::FLOAT => self.console.outfloat(value, self.bits)?,
+            IOPort::FIXED => self.console.outfixed(value, self.bits),
+            IOPort::N_SPECIAL => self.console.fixed_point = Some(value),
             IOPort::X => self.screen.out_x(value),
             IOPort::Y => self.screen.out_y(value),
             IOPort::COLOR => self.screen.out_color(value),
-            IOPort::RNG => crate::srand(value),
-            _ => {todo!("unsupported port {:?}", port)}
+            IOPort::BUFFER => self.screen.out_buffer(value),
+            IOPort::KEY => self.keyboard.selected = value,
+            IOPort::GAMEPAD => self.gamepads.selected = value as usize,
+            IOPort::AXIS => self.gamepads.axis = value as usize,
+            IOPort::ADDR => self.storage.addr = value,
+            IOPort::PAGE => self.storage.page = value,
+            IOPort::BUS => self.storage.out_bus(self.bits, value)?,
+            IOPort::FILE => self.files.out(self.bits, value)?,
+            IOPort::FILE_STATUS => self.files.close()?,
+            IOPort::NOTE => {
+                let event = self.sound.out_note(self.cycles, value);
+                out_sound(event.cycle as f64, event.note as u32, event.instrument as u32);
+            },
+            IOPort::INSTR => if let Some(event) = self.sound.out_instr(self.cycles, value) {
+                out_sound(event.cycle as f64, event.note as u32, event.instrument as u32);
+            },
+            // picks the colour mode, unknown modes are ignored
+            IOPort::G_SPECIAL => if let Some(mode) = screen::ColorMode::from_code(value) {
+                self.screen.set_mode(mode);
+            },
+            IOPort::RNG => self.rng.seed(value),
+            _ => return Err(EmulatorErrorKind::UnsupportedPort { port: _port }),
         }
+        Ok(())
     }
 
     pub fn show(&mut self) {
         self.console.clear_output(10_000);
         jsprintln!("{}", self.console.get_output());
-        out_screen(self.screen.width(), self.screen.height(), self.screen.pixels());
+        out_screen(self.screen.width(), self.screen.height(), &self.screen.pixels());
     }
 
     pub fn new() -> Self {
-        Self { console: Console::new(), screen: Screen::new(32, 32) }
+        Self {
+            console: Console::new(),
+            screen: Screen::new(32, 32),
+            keyboard: input::Keyboard::default(),
+            mouse: input::Mouse::default(),
+            gamepads: input::Gamepads::default(),
+            script: None,
+            storage: storage::Storage::new(1 << 20),
+            files: file::Files::default(),
+            sound: sound::Sound::default(),
+            rng: rng::Rng::default(),
+            cycles: 0,
+            bits: 64,
+        }
+    }
+
+    pub fn input(&mut self, event: input::InputEvent) {
+        use input::InputEvent;
+        match event {
+            InputEvent::KeyDown(key) => self.keyboard.key_down(key),
+            InputEvent::KeyUp(key) => self.keyboard.key_up(key),
+            InputEvent::MouseMove(x, y) => self.mouse.move_to(x, y),
+            InputEvent::MouseButtons(buttons) => self.mouse.buttons = buttons,
+            InputEvent::Wheel(delta) => self.mouse.dwheel += delta,
+            InputEvent::Gamepad(index, pad) => self.gamepads.set(index, pad),
+        }
+    }
+
+    // feeds in the scripted events that are due
+    pub fn run_script(&mut self, step: u64) {
+        let Some(mut script) = self.script.take() else {return};
+        for event in script.due(step) {
+            self.input(event);
+        }
+        if !script.is_empty() {
+            self.script = Some(script);
+        }
+    }
+
+    pub fn set_storage_size(&mut self, words: usize) {
+        self.storage.set_capacity(words);
+    }
+
+    pub fn set_screen_size(&mut self, width: usize, height: usize) {
+        self.screen.resize(width, height);
     }
 }
@@ -0,0 +1,201 @@
+use super::super::emulator::{sign_extend, word_mask};
+
+// %FLOAT is as wide as the word allows, half below 32 bits, single below 64 and double after that
+// theres no float smaller than a half so anything under 16 bits cant have one
+pub const MIN_FLOAT_BITS: u64 = 16;
+
+pub fn float_to_string(value: u64, bits: u64) -> Option<String> {
+    Some(match bits {
+        64.. => format!("{:?}", f64::from_bits(value)),
+        32.. => format!("{:?}", f32::from_bits(value as u32)),
+        MIN_FLOAT_BITS.. => {
+            let half = value as u16;
+            let v = half_to_f64(half);
+            if !v.is_finite() {
+                return Some(format!("{:?}", v as f32));
+            }
+            // the fewest digits that still read back as the same half
+            (1..=5).map(|digits| format!("{:.*e}", digits - 1, v).parse::<f32>().unwrap_or(0.0))
+                .find(|&short| f64_to_half(short as f64) == half)
+                .map_or_else(|| format!("{:?}", v as f32), |short| format!("{:?}", short))
+        },
+        _ => return None,
+    })
+}
+
+// None if the word isnt a number or the width cant hold a float
+pub fn parse_float(word: &str, bits: u64) -> Option<u64> {
+    Some(match bits {
+        64.. => word.parse::<f64>().ok()?.to_bits(),
+        // going through f64 first can round twice
+        32.. => word.parse::<f32>().ok()?.to_bits() as u64,
+        MIN_FLOAT_BITS.. => f64_to_half(word.parse::<f64>().ok()?) as u64,
+        _ => return None,
+    })
+}
+
+fn half_to_f64(half: u16) -> f64 {
+    let sign = if half & 0x8000 != 0 {-1.0} else {1.0};
+    let exp = (half >> 10 & 0x1f) as i32;
+    let frac = (half & 0x3ff) as f64;
+    sign * match exp {
+        0 => frac * 2f64.powi(-24),
+        0x1f if frac == 0.0 => f64::INFINITY,
+        0x1f => f64::NAN,
+        _ => (1.0 + frac / 1024.0) * 2f64.powi(exp - 15),
+    }
+}
+
+// rounds to nearest, ties to even
+fn f64_to_half(v: f64) -> u16 {
+    let sign = if v.is_sign_negative() {0x8000} else {0};
+    let a = v.abs();
+    if a.is_nan() {
+        return sign | 0x7e00;
+    }
+    if a < 2f64.powi(-14) {
+        // subnormal, rounding up to 1024 lands exactly on the smallest normal
+        return sign | (a * 2f64.powi(24)).round_ties_even() as u16;
+    }
+    let mut exp = ((a.to_bits() >> 52) & 0x7ff) as i32 - 1023;
+    let mut frac = ((a / 2f64.powi(exp) - 1.0) * 1024.0).round_ties_even() as u16;
+    if frac == 1024 {
+        exp += 1;
+        frac = 0;
+    }
+    match exp > 15 {
+        true => sign | 0x7c00,
+        false => sign | ((exp + 15) as u16) << 10 | frac,
+    }
+}
+
+// %FIXED is signed with point bits after the binary point, printed exactly
+pub fn fixed_to_string(value: u64, bits: u64, point: u64) -> String {
+    let v = sign_extend(value, bits);
+    let mag = v.unsigned_abs() as u128;
+    let mask = (1u128 << point) - 1;
+    let mut out = format!("{}{}", if v < 0 {"-"} else {""}, mag >> point);
+    let mut frac = mag & mask;
+    if frac != 0 {
+        out.push('.');
+    }
+    // every binary fraction ends within point decimal digits
+    while frac != 0 {
+        frac *= 10;
+        out.push(char::from(b'0' + (frac >> point) as u8));
+        frac &= mask;
+    }
+    out
+}
+
+pub fn parse_fixed(word: &str, bits: u64, point: u64) -> Option<u64> {
+    let (negative, word) = match word.strip_prefix('-') {
+        Some(word) => (true, word),
+        None => (false, word.strip_prefix('+').unwrap_or(word)),
+    };
+    let (int, frac) = word.split_once('.').unwrap_or((word, ""));
+    if int.is_empty() && frac.is_empty() || !frac.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let int = match int {
+        "" => 0,
+        int => int.parse::<u64>().ok()? as u128,
+    };
+    // more digits than this are below what any point can hold anyway
+    let frac = &frac[..frac.len().min(18)];
+    let scale = 10u128.pow(frac.len() as u32);
+    let frac = frac.parse::<u128>().unwrap_or(0);
+    let value = (int << point).wrapping_add(((frac << point) + scale / 2) / scale) as u64;
+    Some(match negative {
+        true => value.wrapping_neg() & word_mask(bits),
+        false => value & word_mask(bits),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_rounds_to_nearest_even() {
+        assert_eq!(f64_to_half(1.0), 0x3c00);
+        assert_eq!(f64_to_half(-2.0), 0xc000);
+        // halfway between 0x3c00 and 0x3c01 goes down to the even one, the next halfway goes up
+        assert_eq!(f64_to_half(1.0 + 2f64.powi(-11)), 0x3c00);
+        assert_eq!(f64_to_half(1.0 + 3.0 * 2f64.powi(-11)), 0x3c02);
+        assert_eq!(f64_to_half(65504.0), 0x7bff);
+        assert_eq!(f64_to_half(65520.0), 0x7c00);
+        assert_eq!(f64_to_half(f64::NEG_INFINITY), 0xfc00);
+        assert_eq!(f64_to_half(f64::NAN) & 0x7c00, 0x7c00);
+    }
+
+    #[test]
+    fn half_subnormal_boundary() {
+        assert_eq!(f64_to_half(2f64.powi(-24)), 0x0001);
+        assert_eq!(f64_to_half(2f64.powi(-25)), 0x0000);
+        assert_eq!(f64_to_half(3.0 * 2f64.powi(-25)), 0x0002);
+        assert_eq!(f64_to_half(1023.0 * 2f64.powi(-24)), 0x03ff);
+        // halfway between the largest subnormal and the smallest normal
+        assert_eq!(f64_to_half(2f64.powi(-14) - 2f64.powi(-25)), 0x0400);
+        assert_eq!(f64_to_half(2f64.powi(-14)), 0x0400);
+        assert_eq!(half_to_f64(0x03ff), 1023.0 * 2f64.powi(-24));
+        assert_eq!(half_to_f64(0x0400), 2f64.powi(-14));
+    }
+
+    #[test]
+    fn every_half_round_trips() {
+        for half in 0..=u16::MAX {
+            let v = half_to_f64(half);
+            if !v.is_nan() {
+                assert_eq!(f64_to_half(v), half, "{:#06x}", half);
+            }
+        }
+    }
+
+    #[test]
+    fn float_width_follows_bits() {
+        assert_eq!(float_to_string(0x3555, 16).as_deref(), Some("0.3333"));
+        assert_eq!(float_to_string(0x3c00, 24).as_deref(), Some("1.0"));
+        assert_eq!(float_to_string(0x7c00, 16).as_deref(), Some("inf"));
+        assert_eq!(float_to_string(0x3fc00000, 32).as_deref(), Some("1.5"));
+        assert_eq!(float_to_string(0.1f64.to_bits(), 64).as_deref(), Some("0.1"));
+        assert_eq!(float_to_string(0x3c, 8), None);
+        assert_eq!(parse_float("0.1", 16), Some(0x2e66));
+        assert_eq!(parse_float("2.5", 32), Some(0x40200000));
+        // just under halfway between two floats, through f64 it would land on halfway and round up
+        assert_eq!(parse_float("1.0000001788139343261718749", 32), Some(0x3f800001));
+        assert_eq!(parse_float("1", 8), None);
+        assert_eq!(parse_float("one", 32), None);
+    }
+
+    #[test]
+    fn fixed_prints_exactly() {
+        assert_eq!(fixed_to_string(0x00018000, 32, 16), "1.5");
+        assert_eq!(fixed_to_string(0xfffe8000, 32, 16), "-1.5");
+        assert_eq!(fixed_to_string(0x0003, 8, 0), "3");
+        assert_eq!(fixed_to_string(0xff, 8, 8), "-0.00390625");
+    }
+
+    #[test]
+    fn fixed_point_64() {
+        assert_eq!(fixed_to_string(1 << 63, 64, 64), "-0.5");
+        assert_eq!(fixed_to_string(1, 64, 64), "0.0000000000000000000542101086242752217003726400434970855712890625");
+        assert_eq!(fixed_to_string(u64::MAX, 64, 64), "-0.0000000000000000000542101086242752217003726400434970855712890625");
+        assert_eq!(parse_fixed("0.25", 64, 64), Some(1 << 62));
+        assert_eq!(parse_fixed("-0.25", 64, 64), Some(0xc000_0000_0000_0000));
+    }
+
+    #[test]
+    fn fixed_parses_and_rounds() {
+        assert_eq!(parse_fixed("1.5", 32, 16), Some(0x00018000));
+        assert_eq!(parse_fixed("-1.5", 32, 16), Some(0xfffe8000));
+        assert_eq!(parse_fixed("+.5", 16, 8), Some(0x0080));
+        assert_eq!(parse_fixed("3.", 16, 8), Some(0x0300));
+        // 0.1 is 1.6 sixteenths, which rounds to 2
+        assert_eq!(parse_fixed("0.1", 8, 4), Some(0x02));
+        assert_eq!(parse_fixed("1.2.3", 16, 8), None);
+        assert_eq!(parse_fixed(".", 16, 8), None);
+        assert_eq!(parse_fixed("-", 16, 8), None);
+        assert_eq!(parse_fixed("1e3", 16, 8), None);
+    }
+}
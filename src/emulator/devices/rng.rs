@@ -0,0 +1,35 @@
+use super::super::emulator::word_mask;
+
+// xorshift, each emulator has its own so runs with the same seed give the same numbers
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rng {
+    // None until the first number, which seeds it from the time
+    state: Option<u64>,
+}
+
+impl Rng {
+    // xorshift gets stuck on 0 so that seed stands for another fixed one
+    pub fn seed(&mut self, seed: u64) {
+        self.state = Some(if seed == 0 {0x9e37_79b9_7f4a_7c15} else {seed});
+    }
+
+    pub fn next(&mut self) -> u64 {
+        let mut x = self.state.unwrap_or_else(|| (crate::now() as u64).max(1));
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = Some(x);
+        x
+    }
+
+    pub fn in_rng(&mut self, bits: u64) -> u64 {
+        self.next() & word_mask(bits)
+    }
+
+    pub fn state(&self) -> Option<u64> {
+        self.state
+    }
+    pub fn restore(&mut self, state: Option<u64>) {
+        self.state = state;
+    }
+}
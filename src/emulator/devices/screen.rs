@@ -1,17 +1,53 @@
+// how the value written to %COLOR turns into a colour
 #[wasm_bindgen::prelude::wasm_bindgen]
-#[derive(Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ColorMode {
+    Rgb888,
+    Rgb565,
+    Rgb332,
+    Grayscale,
+    Palette,
+}
+
+impl ColorMode {
+    // the number a program writes to %G_SPECIAL
+    pub fn from_code(code: u64) -> Option<Self> {
+        Some(match code {
+            0 => Self::Rgb888,
+            1 => Self::Rgb565,
+            2 => Self::Rgb332,
+            3 => Self::Grayscale,
+            4 => Self::Palette,
+            _ => return None,
+        })
+    }
+}
+
+// the 16 colours of the vga text modes
+const DEFAULT_PALETTE: [u32; 16] = [
+    0x000000, 0x0000aa, 0x00aa00, 0x00aaaa, 0xaa0000, 0xaa00aa, 0xaa5500, 0xaaaaaa,
+    0x555555, 0x5555ff, 0x55ff55, 0x55ffff, 0xff5555, 0xff55ff, 0xffff55, 0xffffff,
+];
+
+#[wasm_bindgen::prelude::wasm_bindgen]
+#[derive(Clone, Debug)]
 pub struct Screen {
-    pixels: Vec<u32>, // RGBA
+    pixels: Vec<u32>, // the values the program wrote, decoded when shown
     width: usize,
     height: usize,
     x: usize,
     y: usize,
+    // with %BUFFER on, drawing goes here until the program flushes it
+    buffer: Option<Vec<u32>>,
+    mode: ColorMode,
+    palette: Vec<u32>, // RGB
 }
 
 impl Screen {
     pub fn new(width: usize, height: usize) -> Self {
-        let pixels = vec![0x00_00_00_ff; width*height];
-        Self { pixels, width, height, x: 0, y: 0 }
+        let pixels = vec![0; width*height];
+        Self { pixels, width, height, x: 0, y: 0, buffer: None, mode: ColorMode::Rgb888, palette: DEFAULT_PALETTE.to_vec() }
     }
     pub fn width(&self) -> usize {
         self.width
@@ -19,6 +55,20 @@ impl Screen {
     pub fn height(&self) -> usize {
         self.height
     }
+    // clears the screen but keeps the colour mode and palette
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Self { mode: self.mode, palette: std::mem::take(&mut self.palette), ..Self::new(width, height) };
+    }
+
+    pub fn mode(&self) -> ColorMode {
+        self.mode
+    }
+    pub fn set_mode(&mut self, mode: ColorMode) {
+        self.mode = mode;
+    }
+    pub fn set_palette(&mut self, palette: Vec<u32>) {
+        self.palette = palette;
+    }
 
     pub fn out_x(&mut self, value: u64) {
         self.x = value as usize;
@@ -28,9 +78,71 @@ impl Screen {
     }
     pub fn out_color(&mut self, value: u64) {
         if self.x >= self.width || self.y >= self.height {return;}
-        self.pixels[self.x + self.y * self.width] = ((value as u32) << 8u32).to_be() | 0xff_00_00_00;
+        let i = self.x + self.y * self.width;
+        self.target()[i] = value as u32;
     }
-    pub fn pixels(&self) -> &[u32] {
-        &self.pixels
+    pub fn in_color(&self) -> u64 {
+        if self.x >= self.width || self.y >= self.height {return 0;}
+        let pixels = self.buffer.as_ref().unwrap_or(&self.pixels);
+        pixels[self.x + self.y * self.width] as u64
     }
-}
\ No newline at end of file
+
+    // 0 turns buffering off and shows what was drawn, 1 turns it on, 2 shows the buffer and keeps going
+    pub fn out_buffer(&mut self, value: u64) {
+        match value {
+            0 => if let Some(buffer) = self.buffer.take() {
+                self.pixels = buffer;
+            },
+            1 if self.buffer.is_none() => self.buffer = Some(self.pixels.clone()),
+            2 => if let Some(buffer) = &self.buffer {
+                self.pixels.copy_from_slice(buffer);
+            },
+            _ => {},
+        }
+    }
+
+    fn target(&mut self) -> &mut Vec<u32> {
+        self.buffer.as_mut().unwrap_or(&mut self.pixels)
+    }
+
+    // cursor and the pixel under it, which is all an out to %COLOR can change
+    pub fn undo_point(&self) -> (usize, usize, Option<(usize, u32)>) {
+        let pixels = self.buffer.as_ref().unwrap_or(&self.pixels);
+        let pixel = match self.x < self.width && self.y < self.height {
+            true => Some((self.x + self.y * self.width, pixels[self.x + self.y * self.width])),
+            false => None,
+        };
+        (self.x, self.y, pixel)
+    }
+    pub fn undo(&mut self, x: usize, y: usize, pixel: Option<(usize, u32)>) {
+        self.x = x;
+        self.y = y;
+        if let Some((i, color)) = pixel {
+            self.target()[i] = color;
+        }
+    }
+
+    // 0xRRGGBB for a value in the current mode
+    pub fn decode(&self, value: u32) -> u32 {
+        // stretches an n bit channel to 8 bits
+        fn scale(value: u32, bits: u32) -> u32 {
+            let max = (1 << bits) - 1;
+            (value & max) * 255 / max
+        }
+        match self.mode {
+            ColorMode::Rgb888 => value & 0xff_ff_ff,
+            ColorMode::Rgb565 => scale(value >> 11, 5) << 16 | scale(value >> 5, 6) << 8 | scale(value, 5),
+            ColorMode::Rgb332 => scale(value >> 5, 3) << 16 | scale(value >> 2, 3) << 8 | scale(value, 2),
+            ColorMode::Grayscale => (value & 0xff) * 0x01_01_01,
+            ColorMode::Palette => self.palette.get(value as usize).copied().unwrap_or(0),
+        }
+    }
+
+    // what is on screen as RGBA bytes, the layout ImageData and png want
+    pub fn pixels(&self) -> Vec<u32> {
+        self.pixels.iter().map(|&value| {
+            let rgb = self.decode(value);
+            u32::from_ne_bytes([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 0xff])
+        }).collect()
+    }
+}
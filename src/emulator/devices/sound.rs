@@ -0,0 +1,141 @@
+// the waveform notes are played with, picked with OUT %INSTR
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instrument {
+    Square,
+    Triangle,
+    Sine,
+    Sawtooth,
+    Noise,
+}
+
+impl Instrument {
+    pub fn from_code(code: u64) -> Option<Self> {
+        Some(match code {
+            0 => Self::Square,
+            1 => Self::Triangle,
+            2 => Self::Sine,
+            3 => Self::Sawtooth,
+            4 => Self::Noise,
+            _ => return None,
+        })
+    }
+
+    // phase goes from 0 to 1 over one period
+    fn sample(self, phase: f64, noise: &mut u32) -> f64 {
+        match self {
+            Self::Square => if phase < 0.5 {1.0} else {-1.0},
+            Self::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Self::Sine => (phase * std::f64::consts::TAU).sin(),
+            Self::Sawtooth => 2.0 * phase - 1.0,
+            Self::Noise => {
+                *noise ^= *noise << 13;
+                *noise ^= *noise >> 17;
+                *noise ^= *noise << 5;
+                *noise as f64 / u32::MAX as f64 * 2.0 - 1.0
+            },
+        }
+    }
+}
+
+// what was playing from cycle on
+#[derive(Debug, Clone, Copy)]
+pub struct SoundEvent {
+    pub cycle: u64,
+    pub note: u64,
+    pub instrument: Instrument,
+}
+
+const SAMPLE_RATE: u32 = 44100;
+// so a program that runs for hours cant fill all the memory there is
+const MAX_SECONDS: f64 = 600.0;
+// same for a note loop, later changes still play on the web but dont make it into the wav
+const MAX_EVENTS: usize = 1 << 16;
+
+// one voice, OUT %NOTE plays a midi note (69 is A 440Hz) until the next one and 0 is silence
+#[derive(Debug, Clone)]
+pub struct Sound {
+    pub note: u64,
+    pub instrument: Instrument,
+    events: Vec<SoundEvent>,
+}
+
+impl Default for Sound {
+    fn default() -> Self {
+        Self { note: 0, instrument: Instrument::Square, events: Vec::new() }
+    }
+}
+
+impl Sound {
+    pub fn events(&self) -> &[SoundEvent] {
+        &self.events
+    }
+
+    pub fn out_note(&mut self, cycle: u64, note: u64) -> SoundEvent {
+        self.note = note;
+        self.push(cycle)
+    }
+    // unknown instruments are ignored
+    pub fn out_instr(&mut self, cycle: u64, code: u64) -> Option<SoundEvent> {
+        self.instrument = Instrument::from_code(code)?;
+        Some(self.push(cycle))
+    }
+    fn push(&mut self, cycle: u64) -> SoundEvent {
+        let event = SoundEvent { cycle, note: self.note, instrument: self.instrument };
+        if self.events.len() < MAX_EVENTS {
+            self.events.push(event);
+        }
+        event
+    }
+
+    pub fn undo_point(&self) -> (u64, Instrument, usize) {
+        (self.note, self.instrument, self.events.len())
+    }
+    pub fn undo(&mut self, note: u64, instrument: Instrument, len: usize) {
+        self.note = note;
+        self.instrument = instrument;
+        self.events.truncate(len);
+    }
+
+    // 16 bit mono wav of everything up to cycle end
+    pub fn render_wav(&self, cycles_per_second: f64, end: u64) -> Vec<u8> {
+        let seconds = |cycle: u64| (cycle as f64 / cycles_per_second).min(MAX_SECONDS);
+        let total = (seconds(end) * SAMPLE_RATE as f64) as usize;
+        let mut samples = vec![0i16; total];
+        let (mut phase, mut noise) = (0.0, 0x2545_f491u32);
+        for (i, event) in self.events.iter().enumerate() {
+            let until = self.events.get(i + 1).map_or(end, |next| next.cycle);
+            let start = (seconds(event.cycle) * SAMPLE_RATE as f64) as usize;
+            let stop = ((seconds(until) * SAMPLE_RATE as f64) as usize).min(total);
+            if event.note == 0 || start >= stop {
+                continue;
+            }
+            let freq = 440.0 * 2f64.powf((event.note as f64 - 69.0) / 12.0);
+            for sample in &mut samples[start..stop] {
+                *sample = (event.instrument.sample(phase, &mut noise) * 0.25 * i16::MAX as f64) as i16;
+                phase = (phase + freq / SAMPLE_RATE as f64).fract();
+            }
+        }
+        wav(&samples)
+    }
+}
+
+fn wav(samples: &[i16]) -> Vec<u8> {
+    let data_len = samples.len() as u32 * 2;
+    let mut out = Vec::with_capacity(44 + data_len as usize);
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(36 + data_len).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes()); // pcm
+    out.extend_from_slice(&1u16.to_le_bytes()); // mono
+    out.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    out.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    out.extend_from_slice(&2u16.to_le_bytes());
+    out.extend_from_slice(&16u16.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        out.extend_from_slice(&sample.to_le_bytes());
+    }
+    out
+}
@@ -0,0 +1,78 @@
+use super::super::emulator::{EmulatorErrorKind, word_mask};
+
+// a page is as many words as one word can address, %PAGE picks the page and %ADDR the word in it
+#[derive(Debug, Clone)]
+pub struct Storage {
+    data: Vec<u64>,
+    // in words, data only grows as far as it gets written
+    capacity: usize,
+    pub addr: u64,
+    pub page: u64,
+}
+
+impl Storage {
+    pub fn new(capacity: usize) -> Self {
+        Self { data: Vec::new(), capacity, addr: 0, page: 0 }
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.data.truncate(capacity);
+    }
+
+    fn address(&self, bits: u64) -> u128 {
+        ((self.page as u128) << bits.min(64)) | (self.addr & word_mask(bits)) as u128
+    }
+    fn index(&self, bits: u64) -> Result<usize, EmulatorErrorKind> {
+        let addr = self.address(bits);
+        match addr < self.capacity as u128 {
+            true => Ok(addr as usize),
+            false => Err(EmulatorErrorKind::StorageOutOfBounds { addr }),
+        }
+    }
+
+    pub fn in_bus(&self, bits: u64) -> Result<u64, EmulatorErrorKind> {
+        let i = self.index(bits)?;
+        Ok(self.data.get(i).copied().unwrap_or(0))
+    }
+    pub fn out_bus(&mut self, bits: u64, value: u64) -> Result<(), EmulatorErrorKind> {
+        let i = self.index(bits)?;
+        if i >= self.data.len() {
+            self.data.resize(i + 1, 0);
+        }
+        self.data[i] = value;
+        Ok(())
+    }
+
+    // how many pages fit, what IN %S_SPECIAL reports
+    pub fn pages(&self, bits: u64) -> u64 {
+        let page = word_mask(bits) as u128 + 1;
+        ((self.capacity as u128).div_ceil(page)).min(u64::MAX as u128) as u64
+    }
+
+    // the word a write to %BUS would change and how long data was before it
+    pub fn undo_point(&self, bits: u64) -> Option<(usize, u64, usize)> {
+        let i = self.index(bits).ok()?;
+        Some((i, self.data.get(i).copied().unwrap_or(0), self.data.len()))
+    }
+    pub fn undo(&mut self, i: usize, value: u64, len: usize) {
+        if i < self.data.len() {
+            self.data[i] = value;
+        }
+        self.data.truncate(len);
+    }
+
+    // every word as little endian bytes, as many as the word width needs
+    pub fn to_bytes(&self, bits: u64) -> Vec<u8> {
+        let width = (bits as usize).div_ceil(8);
+        self.data.iter().flat_map(|word| word.to_le_bytes().into_iter().take(width)).collect()
+    }
+    pub fn load_bytes(&mut self, bytes: &[u8], bits: u64) {
+        let width = (bits as usize).div_ceil(8);
+        self.data = bytes.chunks(width).take(self.capacity).map(|chunk| {
+            let mut word = [0; 8];
+            word[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(word) & word_mask(bits)
+        }).collect();
+    }
+}
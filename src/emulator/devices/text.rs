@@ -0,0 +1,192 @@
+use super::IOPort;
+use super::super::emulator::EmulatorErrorKind;
+
+// CHAR5 and CHAR6 only have capitals, lowercase input is read as uppercase
+const CHAR5: &str = " ABCDEFGHIJKLMNOPQRSTUVWXYZ.,!?\n";
+const CHAR6: &str = " ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789.,!?:;'\"-+*/=()<>[]#%&_@$^\n";
+
+// how a text port turns words into characters, TEXT and UTF32 are both one code point per word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Text,
+    Ascii,
+    Char5,
+    Char6,
+    Ascii7,
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+impl Encoding {
+    pub fn from_port(port: IOPort) -> Option<Self> {
+        Some(match port {
+            IOPort::TEXT => Self::Text,
+            IOPort::ASCII => Self::Ascii,
+            IOPort::CHAR5 => Self::Char5,
+            IOPort::CHAR6 => Self::Char6,
+            IOPort::ASCII7 => Self::Ascii7,
+            IOPort::UTF8 => Self::Utf8,
+            IOPort::UTF16 => Self::Utf16,
+            IOPort::UTF32 => Self::Utf32,
+            _ => return None,
+        })
+    }
+    pub fn port(self) -> IOPort {
+        match self {
+            Self::Text => IOPort::TEXT,
+            Self::Ascii => IOPort::ASCII,
+            Self::Char5 => IOPort::CHAR5,
+            Self::Char6 => IOPort::CHAR6,
+            Self::Ascii7 => IOPort::ASCII7,
+            Self::Utf8 => IOPort::UTF8,
+            Self::Utf16 => IOPort::UTF16,
+            Self::Utf32 => IOPort::UTF32,
+        }
+    }
+
+    // the words for one character, None if the encoding doesnt have it
+    pub fn encode(self, c: char) -> Option<Vec<u64>> {
+        let upper = c.to_ascii_uppercase();
+        Some(match self {
+            Self::Text | Self::Utf32 => vec![c as u64],
+            Self::Ascii if (c as u32) < 0x100 => vec![c as u64],
+            Self::Ascii7 if c.is_ascii() => vec![c as u64],
+            Self::Char5 => vec![CHAR5.chars().position(|t| t == upper)? as u64],
+            Self::Char6 => vec![CHAR6.chars().position(|t| t == upper)? as u64],
+            Self::Utf8 => c.encode_utf8(&mut [0; 4]).bytes().map(u64::from).collect(),
+            Self::Utf16 => c.encode_utf16(&mut [0; 2]).iter().map(|&unit| unit as u64).collect(),
+            Self::Ascii | Self::Ascii7 => return None,
+        })
+    }
+}
+
+// what is left over from a character that came in over several words
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Decoder {
+    // code point so far, continuation bytes still to come and how long the sequence is
+    utf8: (u32, u8, u8),
+    // a high surrogate waiting for its low half
+    utf16: Option<u16>,
+}
+
+impl Decoder {
+    // Ok(None) means the character isnt finished yet
+    pub fn decode(&mut self, encoding: Encoding, value: u64) -> Result<Option<char>, EmulatorErrorKind> {
+        let invalid = EmulatorErrorKind::InvalidCharacter { port: encoding.port() as u64, value };
+        let c = match encoding {
+            Encoding::Text | Encoding::Utf32 => u32::try_from(value).ok().and_then(char::from_u32),
+            Encoding::Ascii if value < 0x100 => char::from_u32(value as u32),
+            Encoding::Ascii7 if value < 0x80 => char::from_u32(value as u32),
+            Encoding::Ascii | Encoding::Ascii7 => None,
+            Encoding::Char5 => usize::try_from(value).ok().and_then(|i| CHAR5.chars().nth(i)),
+            Encoding::Char6 => usize::try_from(value).ok().and_then(|i| CHAR6.chars().nth(i)),
+            Encoding::Utf8 => return self.utf8(value).map_err(|_| invalid),
+            Encoding::Utf16 => return self.utf16(value).map_err(|_| invalid),
+        };
+        c.map(Some).ok_or(invalid)
+    }
+
+    fn utf8(&mut self, value: u64) -> Result<Option<char>, ()> {
+        let (code, need, len) = std::mem::take(&mut self.utf8);
+        let byte = u8::try_from(value).map_err(|_| ())?;
+        if need == 0 {
+            return match byte {
+                0x00..=0x7f => Ok(Some(byte as char)),
+                0xc2..=0xdf => {self.utf8 = ((byte & 0x1f) as u32, 1, 2); Ok(None)},
+                0xe0..=0xef => {self.utf8 = ((byte & 0x0f) as u32, 2, 3); Ok(None)},
+                0xf0..=0xf4 => {self.utf8 = ((byte & 0x07) as u32, 3, 4); Ok(None)},
+                _ => Err(()),
+            };
+        }
+        if byte & 0xc0 != 0x80 {
+            return Err(());
+        }
+        let code = code << 6 | (byte & 0x3f) as u32;
+        if need > 1 {
+            self.utf8 = (code, need - 1, len);
+            return Ok(None);
+        }
+        // overlong sequences and surrogates arent valid utf8
+        let shortest = match code {
+            0..=0x7ff => 2,
+            0x800..=0xffff => 3,
+            _ => 4,
+        };
+        match char::from_u32(code) {
+            Some(c) if shortest == len => Ok(Some(c)),
+            _ => Err(()),
+        }
+    }
+
+    fn utf16(&mut self, value: u64) -> Result<Option<char>, ()> {
+        let unit = u16::try_from(value).map_err(|_| ())?;
+        match (self.utf16.take(), unit) {
+            (None, 0xd800..=0xdbff) => {
+                self.utf16 = Some(unit);
+                Ok(None)
+            },
+            (Some(high), 0xdc00..=0xdfff) => {
+                let code = 0x10000 + ((high as u32 - 0xd800) << 10) + (unit as u32 - 0xdc00);
+                Ok(char::from_u32(code))
+            },
+            (None, _) => char::from_u32(unit as u32).map(Some).ok_or(()),
+            (Some(_), _) => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_all(encoding: Encoding, words: &[u64]) -> Result<String, EmulatorErrorKind> {
+        let mut decoder = Decoder::default();
+        let mut out = String::new();
+        for &word in words {
+            out.extend(decoder.decode(encoding, word)?);
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn utf8_streams() {
+        assert_eq!(decode_all(Encoding::Utf8, &[0x48, 0xc3, 0xa9]).unwrap(), "Hé");
+        assert_eq!(decode_all(Encoding::Utf8, &[0xe2, 0x82, 0xac, 0xf0, 0x9f, 0x98, 0x80]).unwrap(), "€😀");
+        assert_eq!(Encoding::Utf8.encode('€'), Some(vec![0xe2, 0x82, 0xac]));
+    }
+
+    #[test]
+    fn utf8_rejects_overlong() {
+        assert!(decode_all(Encoding::Utf8, &[0xc0, 0x80]).is_err());
+        assert!(decode_all(Encoding::Utf8, &[0xe0, 0x80, 0x80]).is_err());
+        assert!(decode_all(Encoding::Utf8, &[0xf0, 0x80, 0x80, 0x80]).is_err());
+    }
+
+    #[test]
+    fn utf8_rejects_surrogates_and_bad_bytes() {
+        assert!(decode_all(Encoding::Utf8, &[0xed, 0xa0, 0x80]).is_err());
+        assert!(decode_all(Encoding::Utf8, &[0xf4, 0x90, 0x80, 0x80]).is_err());
+        assert!(decode_all(Encoding::Utf8, &[0x80]).is_err());
+        assert!(decode_all(Encoding::Utf8, &[0xc3, 0x41]).is_err());
+        assert!(decode_all(Encoding::Utf8, &[0x100]).is_err());
+    }
+
+    #[test]
+    fn utf16_pairs_surrogates() {
+        assert_eq!(decode_all(Encoding::Utf16, &[0x48, 0xd83d, 0xde00]).unwrap(), "H😀");
+        assert_eq!(Encoding::Utf16.encode('😀'), Some(vec![0xd83d, 0xde00]));
+        assert!(decode_all(Encoding::Utf16, &[0xdc00]).is_err());
+        assert!(decode_all(Encoding::Utf16, &[0xd83d, 0x41]).is_err());
+        assert!(decode_all(Encoding::Utf16, &[0x10000]).is_err());
+    }
+
+    #[test]
+    fn small_encodings() {
+        assert_eq!(Encoding::Char5.encode('h'), Some(vec![8]));
+        assert_eq!(Encoding::Char5.encode('1'), None);
+        assert_eq!(decode_all(Encoding::Char6, &[8, 27, 37]).unwrap(), "H0.");
+        assert!(decode_all(Encoding::Ascii7, &[0x80]).is_err());
+        assert_eq!(decode_all(Encoding::Ascii, &[0xe9]).unwrap(), "é");
+    }
+}
@@ -1,21 +1,38 @@
 use devices::DeviceHost;
-use std::{rc::Rc, time::Duration};
+use num_traits::FromPrimitive;
+use std::{collections::HashSet, rc::Rc, time::Duration};
 
 use crate::emulator::ast::Parser;
+use crate::emulator::sources::{SourceMap, SourceProvider, MemorySources};
+use crate::emulator::debugger::Debugger;
+use crate::emulator::journal::{Journal, Change};
+use crate::emulator::tracer::{Tracer, TraceFormat, Record, Written};
+use crate::emulator::profiler::{Profiler, ProfileReport, Frame};
+use crate::emulator::devices::input::{InputEvent, InputScript, Gamepad};
 
 #[allow(unused_imports)]
 use wasm_bindgen::prelude::*;
 
 pub use super::{
     ast::{self, Inst, Operand, Program},
-    lexer, *,
+    *,
 };
 
-#[wasm_bindgen]
 #[derive(Debug, Copy, Clone)]
 pub enum EmulatorErrorKind {
     StackOverflow,
     StackUnderflow,
+    UnsupportedPort{port: u64},
+    MemoryOutOfBounds{addr: u64},
+    InvalidRegister{n: u64},
+    DivisionByZero,
+    PcOutOfRange,
+    StorageOutOfBounds{addr: u128},
+    FileAccessDenied,
+    FileIo,
+    InvalidCharacter{port: u64, value: u64},
+    InvalidNumber{port: u64},
+    UnsupportedBits{port: u64, bits: u64},
 }
 
 impl<'a> std::fmt::Display for EmulatorErrorKind {
@@ -23,10 +40,42 @@ impl<'a> std::fmt::Display for EmulatorErrorKind {
         match self {
             EmulatorErrorKind::StackOverflow => write!(f, "Stack overflow"),
             EmulatorErrorKind::StackUnderflow => write!(f, "Stack underflow"),
+            EmulatorErrorKind::UnsupportedPort { port } => match devices::IOPort::from_u64(*port) {
+                Some(name) => write!(f, "Unsupported port %{:?}", name),
+                None => write!(f, "Unsupported port %{}", port),
+            },
+            EmulatorErrorKind::MemoryOutOfBounds { addr } => write!(f, "Memory address {} is out of bounds", addr),
+            EmulatorErrorKind::InvalidRegister { n } => write!(f, "Register r{} does not exist", n),
+            EmulatorErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            EmulatorErrorKind::PcOutOfRange => write!(f, "Program counter out of range"),
+            EmulatorErrorKind::StorageOutOfBounds { addr } => write!(f, "Storage address {} is out of bounds", addr),
+            EmulatorErrorKind::FileAccessDenied => write!(f, "File is outside the allowed directories"),
+            EmulatorErrorKind::FileIo => write!(f, "File could not be read or written"),
+            EmulatorErrorKind::InvalidCharacter { port, value } => match devices::IOPort::from_u64(*port) {
+                Some(name) => write!(f, "{} is not a valid character for %{:?}", value, name),
+                None => write!(f, "{} is not a valid character", value),
+            },
+            EmulatorErrorKind::InvalidNumber { port } => match devices::IOPort::from_u64(*port) {
+                Some(name) => write!(f, "Input is not a valid number for %{:?}", name),
+                None => write!(f, "Input is not a valid number"),
+            },
+            EmulatorErrorKind::UnsupportedBits { port, bits } => match devices::IOPort::from_u64(*port) {
+                Some(name) => write!(f, "%{:?} does not support {} bit words", name, bits),
+                None => write!(f, "Port {} does not support {} bit words", port, bits),
+            },
         }
     }
 }
 
+// what to do when the program uses a port we dont have a device for
+#[wasm_bindgen]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PortPolicy {
+    Error,
+    WarnOnce,
+    Log,
+}
+
 #[wasm_bindgen]
 #[derive(Debug, Copy, Clone)]
 pub struct EmulatorError(Option<EmulatorErrorKind>);
@@ -44,8 +93,24 @@ pub struct EmulatorState {
     heap: Vec<u64>,
     stack: Stack,
     pc: usize,
+    // instructions run so far
+    steps: u64,
+    // the virtual clock, one cycle per instruction plus however long OUT %WAIT waited
+    cycles: u64,
+    clock_speed: u64,
+    // OUT %WAIT holds everything until the clock gets here
+    wait_until: u64,
+    bits: u64,
+    mask: u64,
     program: Program,
     devices: DeviceHost,
+    port_policy: PortPolicy,
+    warned_ports: HashSet<u64>,
+    warnings: String,
+    debugger: Debugger,
+    journal: Journal,
+    tracer: Tracer,
+    profiler: Profiler,
     pub error: EmulatorError,
 }
 
@@ -63,21 +128,36 @@ impl Stack {
         Stack { data, sp: size as i64 - 1, size }
     }
 
-    fn push(&mut self, data: u64) -> Result<(), EmulatorError> {
-        if self.sp > 0 {
-            self.data[self.sp as usize] = data;
-            self.sp -= 1;
-            Ok(())
+    fn push(&mut self, data: u64) -> Result<(), EmulatorErrorKind> {
+        let (i, _) = self.top_slot()?;
+        self.data[i] = data;
+        self.sp -= 1;
+        Ok(())
+    }
+    // the slot a push would write to, or why it cant
+    fn top_slot(&self) -> Result<(usize, u64), EmulatorErrorKind> {
+        if self.sp <= 0 {
+            Err(EmulatorErrorKind::StackOverflow)
+        } else if self.sp >= self.size as i64 {
+            // sp was moved out of the stack with MOV SP
+            Err(EmulatorErrorKind::MemoryOutOfBounds { addr: self.sp as u64 })
         } else {
-            Err(EmulatorError(Some(EmulatorErrorKind::StackOverflow)))
+            Ok((self.sp as usize, self.data[self.sp as usize]))
         }
     }
-    fn pop(&mut self) -> Result<u64, EmulatorError> {
-        if self.sp < self.size as i64 - 1 {
-            self.sp += 1;
-            Ok(self.data[self.sp as usize])
+    fn pop(&mut self) -> Result<u64, EmulatorErrorKind> {
+        let v = self.peek()?;
+        self.sp += 1;
+        Ok(v)
+    }
+    // what a pop would return, or why it cant
+    fn peek(&self) -> Result<u64, EmulatorErrorKind> {
+        if self.sp >= self.size as i64 - 1 {
+            Err(EmulatorErrorKind::StackUnderflow)
+        } else if self.sp < -1 {
+            Err(EmulatorErrorKind::MemoryOutOfBounds { addr: self.sp as u64 + 1 })
         } else {
-            Err(EmulatorError(Some(EmulatorErrorKind::StackUnderflow)))
+            Ok(self.data[self.sp as usize + 1])
         }
     }
 }
@@ -89,15 +169,51 @@ pub enum StepResult {
     HLT,
     Input,
     Error,
+    Breakpoint,
+    // OUT %WAIT is holding the program until enough virtual time passes
+    Waiting,
 }
 
+// cycles per second, run_for_ms wont run faster than this
+pub const DEFAULT_CLOCK_SPEED: u64 = 10_000_000;
+
 pub const PC: u64 = u64::MAX;
 pub const SP: u64 = u64::MAX - 1;
 
-fn does_overflow(a: u64, b: u64) -> bool {
-    match a.checked_add(b) {
-        Some(_) => false,
-        None => true,
+fn does_overflow(a: u64, b: u64, mask: u64) -> bool {
+    a as u128 + b as u128 > mask as u128
+}
+
+pub fn word_mask(bits: u64) -> u64 {
+    if bits >= 64 {
+        u64::MAX
+    } else {
+        (1 << bits) - 1
+    }
+}
+
+// how an operand read is interpreted at the current word width
+trait Word {
+    fn from_word(value: u64, bits: u64) -> Self;
+}
+impl Word for u64 {
+    fn from_word(value: u64, _bits: u64) -> Self { value }
+}
+impl Word for usize {
+    fn from_word(value: u64, _bits: u64) -> Self { value as usize }
+}
+impl Word for i64 {
+    fn from_word(value: u64, bits: u64) -> Self {
+        sign_extend(value, bits)
+    }
+}
+
+pub fn sign_extend(value: u64, bits: u64) -> i64 {
+    if bits >= 64 || bits == 0 {
+        value as i64
+    } else {
+        let shift = 64 - bits as u32;
+        ((value << shift) as i64) >> shift
     }
 }
 
@@ -107,12 +223,15 @@ unsafe impl Send for EmulatorState {}
 #[wasm_bindgen]
 #[allow(dead_code)]
 impl EmulatorState {
-    fn new(program: Program, devices: DeviceHost) -> Self {
+    fn new(program: Program, mut devices: DeviceHost) -> Self {
+        let bits = program.headers.bits;
+        devices.bits = bits;
+        let mask = word_mask(bits);
         let regs = vec![0; program.headers.minreg as usize];
         let mut heap = vec![0; (program.memory.len() as u64 + program.headers.minheap) as usize];
 
         for (i, el) in program.memory.iter().enumerate() {
-            heap[i] = *el;
+            heap[i] = *el & mask;
         }
 
         EmulatorState {
@@ -120,24 +239,434 @@ impl EmulatorState {
             heap,
             stack: Stack::new(program.headers.minstack as usize),
             pc: 0,
+            steps: 0,
+            cycles: 0,
+            clock_speed: DEFAULT_CLOCK_SPEED,
+            wait_until: 0,
+            bits,
+            mask,
             program,
             devices,
+            port_policy: PortPolicy::Error,
+            warned_ports: HashSet::new(),
+            warnings: String::new(),
+            debugger: Debugger::new(),
+            journal: Journal::new(),
+            tracer: Tracer::new(),
+            profiler: Profiler::new(),
             error: EmulatorError::new(),
         }
     }
 
+    pub fn set_port_policy(&mut self, policy: PortPolicy) {
+        self.port_policy = policy;
+    }
+
+    pub fn get_warnings(&self) -> String {
+        self.warnings.clone()
+    }
+
+    // whether a port error stops the program, the rest only warn and read as 0
+    fn port_fatal(&self, err: &EmulatorErrorKind) -> bool {
+        !matches!(err, EmulatorErrorKind::UnsupportedPort { .. }) || self.port_policy == PortPolicy::Error
+    }
+
+    // the port errors port_fatal lets through only warn
+    fn unsupported_port(&mut self, err: EmulatorErrorKind) {
+        let EmulatorErrorKind::UnsupportedPort { port } = err else {
+            return;
+        };
+        match self.port_policy {
+            PortPolicy::Error => {},
+            PortPolicy::WarnOnce if !self.warned_ports.insert(port) => {},
+            PortPolicy::WarnOnce | PortPolicy::Log => {
+                let mut out = String::new();
+                let (lineno, line, col, span) = self.source_at(self.pc);
+                out_emu_warn(&mut out, &err, &lineno, line, col, span);
+                self.warnings.push_str(&out);
+            },
+        }
+    }
+
+    // line number, line text, column and instruction text for a pc
+    fn source_at(&self, pc: usize) -> (String, &str, usize, &str) {
+        let sources = &self.program.debug.sources;
+        let src = &sources.text;
+        let lineno = self.program.debug.pc_to_line_start.get(pc).copied().unwrap_or(0);
+        match self.program.debug.pc_to_span.get(pc) {
+            Some(span) => {
+                // macro bodies and included files are shown where they were written
+                let lineno = sources.lineno(span.start);
+                let span = &src[span.clone()];
+                let (line, col) = errorcontext::span_line(src, span);
+                (lineno, line, col, span)
+            },
+            None => (format!("{} ", lineno), "", 0, ""),
+        }
+    }
+
+    fn snapshot(&self) -> String {
+        use std::fmt::Write;
+        let mut out = String::new();
+        writeln!(out, "PC: {}, SP: {}", self.pc, self.stack.sp).unwrap();
+        let regs: Vec<String> = self.regs.iter().enumerate().map(|(i, v)| format!("r{}: {}", i + 1, v)).collect();
+        writeln!(out, "Regs: {}", regs.join(", ")).unwrap();
+        let top = (self.stack.sp + 1).clamp(0, self.stack.size as i64) as usize;
+        let stack: Vec<String> = self.stack.data[top..].iter().take(8).map(|v| v.to_string()).collect();
+        let more = if self.stack.size - top > 8 {", ..."} else {""};
+        writeln!(out, "Stack: [{}{}]", stack.join(", "), more).unwrap();
+        out
+    }
+
+    fn show_output(&mut self) {
+        self.devices.show();
+        if !self.warnings.is_empty() {
+            jsprintln!("{}", self.warnings);
+        }
+        if let Some(err) = self.get_err() {
+            jsprintln!("{}", err);
+        }
+        if let Some(reason) = self.get_break_reason() {
+            jsprintln!("{}", reason);
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.debugger.add_breakpoint(pc);
+    }
+    pub fn remove_breakpoint(&mut self, pc: usize) {
+        self.debugger.remove_breakpoint(pc);
+    }
+    // breaks on the first line with code at or after line, returns that line
+    pub fn add_line_breakpoint(&mut self, line: usize) -> Option<usize> {
+        let lines = &self.program.debug.pc_to_line_start;
+        let line = lines.iter().copied().filter(|l| *l >= line).min()?;
+        // a macro call spans several instructions, only stop at the first
+        let pcs = (0..lines.len())
+            .filter(|pc| lines[*pc] == line && (*pc == 0 || lines[pc - 1] != line))
+            .collect();
+        self.debugger.add_line_breakpoint(line, pcs);
+        Some(line)
+    }
+    pub fn remove_line_breakpoint(&mut self, line: usize) {
+        self.debugger.remove_line_breakpoint(line);
+    }
+    pub fn watch_memory(&mut self, addr: u64, read: bool, write: bool) {
+        self.debugger.watch_memory(addr, read, write);
+    }
+    pub fn watch_register(&mut self, reg: u64, watch: bool) {
+        self.debugger.watch_register(reg, watch);
+    }
+    pub fn clear_breakpoints(&mut self) {
+        self.debugger.clear();
+    }
+    // stops before the next instruction
+    pub fn pause(&mut self) {
+        self.debugger.pause();
+    }
+
+    // these only arm the debugger, run or run_for_ms then stops with StepResult::Breakpoint
+    pub fn step_in(&mut self) {
+        self.debugger.step();
+    }
+    pub fn step_over(&mut self) {
+        match self.program.instructions.get(self.pc) {
+            Some(Inst::CAL(_)) => self.debugger.step_over(self.pc + 1, self.stack.sp),
+            _ => self.debugger.step(),
+        }
+    }
+    pub fn step_out(&mut self) {
+        self.debugger.step_out();
+    }
+
+    // how many steps step_back can undo, 0 turns the journal off which is the default
+    pub fn set_journal_capacity(&mut self, capacity: usize) {
+        self.journal.set_capacity(capacity);
+    }
+    pub fn journal_len(&self) -> usize {
+        self.journal.len()
+    }
+
+    // undoes the last step, false when the journal has nothing left
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.journal.pop() else {
+            return false;
+        };
+        for change in entry.changes.into_iter().rev() {
+            match change {
+                Change::Reg(i, v) => self.regs[i] = v,
+                Change::Heap(i, v) => self.heap[i] = v,
+                Change::Stack(i, v) => self.stack.data[i] = v,
+                Change::Device(undo) => self.devices.undo(undo),
+                Change::Wait(until) => self.wait_until = until,
+            }
+        }
+        self.pc = entry.pc;
+        self.stack.sp = entry.sp;
+        self.cycles = entry.cycles;
+        self.steps = self.steps.saturating_sub(1);
+        self.error = EmulatorError::new();
+        self.debugger.rewound();
+        true
+    }
+    // steps back until the pc is at pc, false when the journal ran out first
+    pub fn run_back_to(&mut self, pc: usize) -> bool {
+        while self.step_back() {
+            if self.pc == pc {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn get_break_reason(&self) -> Option<String> {
+        let reason = self.debugger.hit.as_ref()?;
+        Some(format!("{}\n{}", reason, self.get_location()))
+    }
+    // the line about to run and the registers and stack
+    pub fn get_location(&self) -> String {
+        let (lineno, line, _, _) = self.source_at(self.pc);
+        format!("\t{}| {}\n{}", lineno, line.trim(), self.snapshot())
+    }
+
+    pub fn get_pc(&self) -> usize {
+        self.pc
+    }
+    pub fn get_sp(&self) -> i64 {
+        self.stack.sp
+    }
+    pub fn get_steps(&self) -> u64 {
+        self.steps
+    }
+
+    // bits after the binary point for %FIXED, a program can change it with OUT %N_SPECIAL
+    pub fn set_fixed_point(&mut self, point: u32) {
+        self.devices.console.fixed_point = Some(point as u64);
+    }
+
+    // makes %RNG give the same numbers every run, a program can still reseed it with OUT %RNG
+    pub fn set_seed(&mut self, seed: u64) {
+        self.devices.rng.seed(seed);
+    }
+
+    // cycles per second, at least 1
+    pub fn set_clock_speed(&mut self, hz: f64) {
+        self.clock_speed = (hz as u64).max(1);
+    }
+    pub fn get_clock_speed(&self) -> f64 {
+        self.clock_speed as f64
+    }
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+    // virtual time since the program started, what IN %WAIT reads
+    pub fn elapsed_ms(&self) -> f64 {
+        self.cycles as f64 * 1000.0 / self.clock_speed as f64
+    }
+    fn ms_to_cycles(&self, ms: f64) -> u64 {
+        (ms * self.clock_speed as f64 / 1000.0) as u64
+    }
+    // how much virtual time OUT %WAIT still holds the program for
+    pub fn wait_remaining_ms(&self) -> f64 {
+        self.wait_until.saturating_sub(self.cycles) as f64 * 1000.0 / self.clock_speed as f64
+    }
+    // lets virtual time pass, only while waiting since running instructions is what moves the clock otherwise
+    pub fn advance_clock(&mut self, ms: f64) {
+        if self.cycles < self.wait_until {
+            self.cycles = self.cycles.saturating_add(self.ms_to_cycles(ms)).min(self.wait_until);
+        }
+    }
+    // ends the wait right away, for hosts that dont care about real time
+    pub fn skip_wait(&mut self) {
+        self.cycles = self.cycles.max(self.wait_until);
+    }
+    pub fn get_bits(&self) -> u64 {
+        self.bits
+    }
+    pub fn reg_count(&self) -> usize {
+        self.regs.len()
+    }
+    // uses urcl numbering so 1 is r1, r0 is always 0
+    pub fn get_reg(&self, reg: u64) -> Option<u64> {
+        match reg {
+            PC => Some(self.pc as u64),
+            SP => Some(self.stack.sp as u64),
+            0 => Some(0),
+            _ => self.regs.get(reg as usize - 1).copied(),
+        }
+    }
+    pub fn get_regs(&self) -> Vec<u64> {
+        self.regs.clone()
+    }
+    pub fn heap_len(&self) -> usize {
+        self.heap.len()
+    }
+    // heap followed by the stack, same addresses LOD and STR use
+    pub fn memory_len(&self) -> usize {
+        self.heap.len() + self.stack.data.len()
+    }
+    // cut short at the end of memory instead of failing
+    pub fn get_memory(&self, start: usize, len: usize) -> Vec<u64> {
+        let end = start.saturating_add(len).min(self.memory_len());
+        let start = start.min(end);
+        let heap_len = self.heap.len();
+        let mut out = Vec::with_capacity(end - start);
+        out.extend_from_slice(&self.heap[start.min(heap_len)..end.min(heap_len)]);
+        out.extend_from_slice(&self.stack.data[start.max(heap_len) - heap_len..end.max(heap_len) - heap_len]);
+        out
+    }
+    // everything pushed so far, the top of the stack first
+    pub fn get_stack(&self) -> Vec<u64> {
+        let top = (self.stack.sp + 1).clamp(0, self.stack.size as i64) as usize;
+        self.stack.data[top..].to_vec()
+    }
+    pub fn disassemble(&self, pc: usize) -> Option<String> {
+        self.program.instructions.get(pc).map(|inst| inst.to_string())
+    }
+    pub fn current_instruction(&self) -> Option<String> {
+        self.disassemble(self.pc)
+    }
+
+    pub fn start_trace(&mut self, format: TraceFormat) {
+        self.tracer.start(format);
+    }
+    pub fn stop_trace(&mut self) {
+        self.tracer.stop();
+    }
+    // only trace pcs in start..end
+    pub fn trace_pcs(&mut self, start: usize, end: usize) {
+        self.tracer.set_pcs(Some(start..end));
+    }
+    pub fn trace_all_pcs(&mut self) {
+        self.tracer.set_pcs(None);
+    }
+    // with no classes picked every instruction is traced
+    pub fn trace_class(&mut self, class: ast::InstClass, traced: bool) {
+        self.tracer.set_class(class, traced);
+    }
+    // everything traced since the last call, unless a writer was given with trace_to
+    pub fn take_trace(&mut self) -> Vec<u8> {
+        self.tracer.take_output()
+    }
+
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiler.set_enabled(enabled);
+    }
+    pub fn reset_profile(&mut self) {
+        self.profiler.reset();
+    }
+    // how often each instruction ran, indexed by pc
+    pub fn instruction_counts(&self) -> Vec<u64> {
+        let mut counts = self.profiler.counts().to_vec();
+        counts.resize(self.program.instructions.len(), 0);
+        counts
+    }
+    pub fn profile_report(&self, report: ProfileReport) -> String {
+        let name = |frame| self.frame_name(frame);
+        match report {
+            ProfileReport::Flat => self.flat_profile(),
+            ProfileReport::Graph => self.profiler.call_graph(&name),
+            ProfileReport::Folded => self.profiler.folded(&name),
+        }
+    }
+
+    // the label at or before pc, macro locals lose their expansion id
+    fn label_at(&self, pc: usize, exact: bool) -> Option<&str> {
+        let labels = &self.program.debug.code_labels;
+        let (at, name) = &labels[labels.partition_point(|(at, _)| *at <= pc).checked_sub(1)?];
+        match exact && *at != pc {
+            true => None,
+            false => Some(name.split('@').next().unwrap_or(name)),
+        }
+    }
+
+    fn frame_name(&self, frame: Frame) -> String {
+        match frame {
+            Frame::Root => "main".to_string(),
+            Frame::Call(pc) => self.label_at(pc, false).map_or_else(|| format!("pc {}", pc), str::to_string),
+            Frame::Region(id) => self.label_at(id as usize, true).map_or_else(|| format!("region {}", id), str::to_string),
+        }
+    }
+
+    fn flat_profile(&self) -> String {
+        use std::fmt::Write;
+        let mut out = String::new();
+        writeln!(out, "Functions\n{:>10} {:>10}  name", "total", "self").unwrap();
+        for (name, self_cycles, total) in self.profiler.functions(&|frame| self.frame_name(frame)) {
+            writeln!(out, "{:>10} {:>10}  {}", total, self_cycles, name).unwrap();
+        }
+
+        let counts = self.instruction_counts();
+        let mut lines: Vec<(String, &str, u64)> = Vec::new();
+        for (pc, &count) in counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            let (lineno, line, _, _) = self.source_at(pc);
+            let lineno = lineno.trim_end().to_string();
+            match lines.iter_mut().find(|(l, _, _)| *l == lineno) {
+                Some(entry) => entry.2 += count,
+                None => lines.push((lineno, line.trim(), count)),
+            }
+        }
+        lines.sort_by_key(|&(_, _, count)| std::cmp::Reverse(count));
+        writeln!(out, "\nLines\n{:>10} {:>6}|", "count", "line").unwrap();
+        for (lineno, line, count) in lines {
+            writeln!(out, "{:>10} {:>6}| {}", count, lineno, line).unwrap();
+        }
+
+        let mut insts: Vec<(usize, u64)> = counts.into_iter().enumerate().filter(|&(_, count)| count > 0).collect();
+        insts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(out, "\nInstructions\n{:>10} {:>6}  instruction", "count", "pc").unwrap();
+        for (pc, count) in insts {
+            writeln!(out, "{:>10} {:>6}  {}", count, pc, self.program.instructions[pc]).unwrap();
+        }
+        out
+    }
+
+    fn trace_record(&self, pc: usize) -> Option<Record> {
+        let inst = self.program.instructions.get(pc)?;
+        if !self.tracer.wants(pc, inst.class()) {
+            return None;
+        }
+        let sources = &self.program.debug.sources;
+        let (file, line) = match self.program.debug.pc_to_span.get(pc) {
+            Some(span) => sources.locate(span.start),
+            None => (0, self.program.debug.pc_to_line_start.get(pc).copied().unwrap_or(0)),
+        };
+        let name = &sources.files[file].name;
+        Some(Record {
+            pc,
+            file: name.clone(),
+            line,
+            // same as diagnostics show it, without going through source_at
+            lineno: match file {
+                0 => line.to_string(),
+                _ => format!("{}:{}", name, line),
+            },
+            inst: inst.to_string(),
+            reads: Vec::new(),
+            writes: Vec::new(),
+        })
+    }
+
     pub fn get_output(&self) -> String {
         self.devices.console.get_output().to_string()
     }
 
+    pub fn clear_output(&mut self) {
+        self.devices.console.clear_output(0);
+        self.warnings.clear();
+    }
+
+    pub fn push_input(&mut self, text: &str) {
+        self.devices.console.push_input(text);
+    }
+
     pub fn run(&mut self) -> StepResult {
         loop {
             let result = self.step();
             match result {
                 StepResult::Continue => (),
-                StepResult::Error => return result,
                 _ => {
-                    self.devices.show();
+                    self.show_output();
                     return result;
                 }
             }
@@ -148,22 +677,37 @@ impl EmulatorState {
     }
     pub fn show(&mut self) {
         clear_text();
-        self.devices.show();
-        jsprintln!("Regs: {:?},\nMem: {:?},\nStack: {:?}", self.regs, self.heap, self.stack.data);
+        self.show_output();
+        // the full heap can be huge, hosts page through it with get_memory instead
+        jsprintln!("{}", self.snapshot());
     }
 
+    // runs at most max_time_ms of virtual time, or less if the host cant keep up with the clock
     pub fn run_for_ms(&mut self, max_time_ms: f64) -> StepResult {
         const BURST_LENGTH: u32 = 1024;
         let start = now();
         let end = start + max_time_ms;
-        while now() < end {
+        let until = self.cycles.saturating_add(self.ms_to_cycles(max_time_ms));
+        while now() < end && self.cycles < until {
             for _ in 0..BURST_LENGTH {
-                let result = self.step();
+                if self.cycles >= until {
+                    break;
+                }
+                let result = match self.step() {
+                    // the time that was left to run passes waiting
+                    StepResult::Waiting => {
+                        self.cycles = self.wait_until.min(until);
+                        match self.cycles < self.wait_until {
+                            true => StepResult::Waiting,
+                            false => continue,
+                        }
+                    },
+                    result => result,
+                };
                 match result {
                     StepResult::Continue => (),
-                    StepResult::Error => return result,
                     _ => {
-                        self.devices.show();
+                        self.show_output();
                         return result;
                     }
                 }
@@ -181,74 +725,221 @@ impl EmulatorState {
     // is there some cargo library for that or should we just do some Worker schenenigans
 
     pub fn step(&mut self) -> StepResult {
+        if self.cycles < self.wait_until {
+            return StepResult::Waiting;
+        }
+        let pc = self.pc;
+        if self.debugger.before(pc, self.stack.sp) {
+            return StepResult::Breakpoint;
+        }
+        if self.devices.script.is_some() {
+            self.devices.run_script(self.steps);
+        }
+        if self.journal.enabled() {
+            self.journal.begin(pc, self.stack.sp, self.cycles);
+        }
+        if self.tracer.enabled() {
+            if let Some(record) = self.trace_record(pc) {
+                self.tracer.begin(record);
+            }
+        }
+        if self.profiler.enabled() {
+            self.profiler.count(pc);
+        }
+        let watched_regs: Vec<(u64, u64)> = match self.debugger.watches_registers() {
+            true => self.debugger.watched_registers().map(|r| (r, self.regs.get((r as usize).wrapping_sub(1)).copied().unwrap_or(0))).collect(),
+            false => Vec::new(),
+        };
         let Some(inst) = self.program.instructions.get(self.pc) else {
-            return StepResult::HLT
+            // running off the end halts, anything further out was a bad jump
+            if self.pc == self.program.instructions.len() {
+                return StepResult::HLT
+            }
+            self.error = EmulatorError(Some(EmulatorErrorKind::PcOutOfRange));
+            return StepResult::Error
         };
 
+        macro_rules! journal {
+            ($change:expr) => {
+                if self.journal.enabled() {
+                    self.journal.record($change);
+                }
+            };
+        }
+
+        // device access goes through these so the journal can undo it
+        macro_rules! port_in {
+            ($port:expr) => {{
+                let port: u64 = $port;
+                self.devices.cycles = self.cycles;
+                match self.journal.enabled() {
+                    false => self.devices.in_port(port),
+                    true => {
+                        let undo = self.devices.undo_point(port, true);
+                        let result = self.devices.in_port(port);
+                        self.journal.record(Change::Device(self.devices.undo_done(undo)));
+                        result
+                    },
+                }
+            }};
+        }
+        macro_rules! port_out {
+            ($port:expr, $value:expr) => {{
+                let port: u64 = $port;
+                let value: u64 = $value;
+                self.devices.cycles = self.cycles;
+                match self.journal.enabled() {
+                    false => self.devices.out(port, value),
+                    true => {
+                        let undo = self.devices.undo_point(port, false);
+                        let result = self.devices.out(port, value);
+                        self.journal.record(Change::Device(self.devices.undo_done(undo)));
+                        result
+                    },
+                }
+            }};
+        }
+
+        macro_rules! trace {
+            ($written:expr) => {
+                if self.tracer.active() {
+                    self.tracer.write($written);
+                }
+            };
+        }
+
+        // a failing instruction stops right there, before it writes a register, memory or a port
+        macro_rules! fail {
+            ($kind:expr) => {{
+                if self.error.0.is_none() {
+                    self.error = EmulatorError(Some($kind));
+                }
+                if self.journal.enabled() {
+                    self.journal.commit();
+                }
+                if self.tracer.active() {
+                    self.tracer.commit();
+                }
+                // leave the pc on the instruction that failed
+                self.pc = pc;
+                return StepResult::Error
+            }};
+        }
+
+        // jumping to the end of the program halts, anything past it is an error
+        macro_rules! branch {
+            ($dest:ident $(if $cond:expr)?) => {
+                match () {
+                    () $(if $cond)? => if $dest > self.program.instructions.len() {
+                        fail!(EmulatorErrorKind::PcOutOfRange)
+                    } else {
+                        self.pc = $dest.wrapping_sub(1)
+                    },
+                    #[allow(unreachable_patterns)]
+                    () => (),
+                }
+            }
+        }
+
         macro_rules! get {
             ($operand:expr) => {
                 match $operand {
-                    Operand::Imm(v) => *v,
+                    Operand::Imm(v) => *v & self.mask,
                     Operand::Reg(v) => match *v {
                         PC => self.pc as u64,
                         SP => self.stack.sp as u64,
                         0  => 0,
-                        _  => self.regs[*v as usize - 1],
+                        _  => match self.regs.get(*v as usize - 1) {
+                            Some(value) => *value,
+                            None => fail!(EmulatorErrorKind::InvalidRegister { n: *v }),
+                        },
                     },
                     _ => panic!("Unsupported operand {:?}", $operand),
                 }
             };
         }
+        macro_rules! read {
+            ($operand:expr) => {{
+                let value = get!($operand);
+                if self.tracer.active() {
+                    self.tracer.read(value);
+                }
+                value
+            }};
+        }
         macro_rules! set {
             ($operand:expr, $value:expr) => {
                 match $operand {
                     Operand::Imm(_) => {} // do nothing assume it is r0
                     Operand::Reg(v) => match *v {
-                        PC => self.pc = $value as usize,
-                        SP => self.stack.sp = $value as i64,
+                        // same as a jump
+                        PC => {
+                            let dest = $value as usize;
+                            branch!(dest)
+                        },
+                        SP => {
+                            trace!(Written::Reg(SP, $value));
+                            self.stack.sp = $value as i64
+                        },
                         0  => {},
-                        _  => self.regs[*v as usize - 1] = $value,
+                        _  => match self.regs.get_mut(*v as usize - 1) {
+                            Some(reg) => {
+                                journal!(Change::Reg(*v as usize - 1, *reg));
+                                trace!(Written::Reg(*v, $value));
+                                *reg = $value
+                            },
+                            None => fail!(EmulatorErrorKind::InvalidRegister { n: *v }),
+                        },
                     },
                     _ => panic!("Unsupported target operand {:?}", $operand),
                 }
             };
         }
 
+        // addresses past the heap go into the stack
         macro_rules! get_mem {
-            ($index:expr) => {
-                if $index < self.program.headers.minheap {
-                    self.heap[$index as usize]
+            ($index:expr) => {{
+                let index: u64 = $index;
+                let heap_len = self.heap.len() as u64;
+                if index >= heap_len + self.stack.data.len() as u64 {
+                    fail!(EmulatorErrorKind::MemoryOutOfBounds { addr: index })
+                }
+                if self.debugger.watches_memory() {
+                    self.debugger.mem_read(index);
+                }
+                if index < heap_len {
+                    self.heap[index as usize]
                 } else {
-                    self.stack.data[($index - self.program.headers.minheap) as usize]
+                    self.stack.data[(index - heap_len) as usize]
                 }
-            };
+            }};
         }
         macro_rules! set_mem {
-            ($index:expr, $value:expr) => {
-                if $index < self.program.headers.minheap {
-                    self.heap[$index as usize] = $value
-                } else {
-                    self.stack.data[($index - self.program.headers.minheap) as usize] = $value
+            ($index:expr, $value:expr) => {{
+                let index: u64 = $index;
+                let value: u64 = $value;
+                let heap_len = self.heap.len() as u64;
+                if index >= heap_len + self.stack.data.len() as u64 {
+                    fail!(EmulatorErrorKind::MemoryOutOfBounds { addr: index })
                 }
-            };
-        }
-
-        macro_rules! getm {
-            ($operand:expr) => {
-                if get!($operand) < self.program.headers.minheap {
-                    self.heap[get!($operand) as usize]
+                if self.debugger.watches_memory() {
+                    self.debugger.mem_write(index, value);
+                }
+                trace!(Written::Mem(index, value));
+                if index < heap_len {
+                    journal!(Change::Heap(index as usize, self.heap[index as usize]));
+                    self.heap[index as usize] = value
                 } else {
-                    self.stack.data[(get!($operand) - self.program.headers.minheap) as usize]
+                    let i = (index - heap_len) as usize;
+                    journal!(Change::Stack(i, self.stack.data[i]));
+                    self.stack.data[i] = value
                 }
-            };
+            }};
         }
+
         macro_rules! setm {
             ($operand:expr, $value:expr) => {
-                if get!($operand) < self.program.headers.minheap {
-                    self.heap[get!($operand) as usize] = $value
-                } else {
-                    self.stack.data[(get!($operand) - self.program.headers.minheap) as usize] = $value
-                }
+                set_mem!(get!($operand), $value)
             };
         }
 
@@ -265,23 +956,46 @@ impl EmulatorState {
             (@read) => {};
             (@read [$name:ident$(: $type:ty)?]$(, $($rest:tt)*)?) => {
                 #[allow(unused_variables)]
-                let $name = getm!($name) $(as $type)?;
+                let $name = get_mem!(read!($name));
+                $(let $name = <$type as Word>::from_word($name, self.bits);)?
                 insts!(@read $($($rest)*)?)
             };
             (@read $name:ident$(: $type:ty)?$(, $($rest:tt)*)?) => {
                 #[allow(unused_variables)]
-                let $name = get!($name) $(as $type)?;
+                let $name = read!($name);
+                $(let $name = <$type as Word>::from_word($name, self.bits);)?
                 insts!(@read $($($rest)*)?)
             };
+            // the register being assigned to is always first and isnt traced as a read
+            (@run ($to:ident) ($first:ident$(, $($rest:tt)*)?) $body:expr) => {
+                insts!(@assign $to; {
+                    #[allow(unused_variables)]
+                    let $first = get!($first);
+                    insts!(@read $($($rest)*)?);
+                    $body
+                })
+            };
+            (@run ($($to:tt)?) ($($ops:tt)*) $body:expr) => {
+                insts!(@assign $($to)?; {
+                    insts!(@read $($ops)*);
+                    $body
+                })
+            };
             (@assign; $body:expr) => {
                 $body
             };
             (@assign $to:ident; $body:expr) => {{
-                let value = $body as u64;
+                // a bad target register fails before POP or IN take anything
+                if let Operand::Reg(n @ 1..) = *$to {
+                    if n as usize > self.regs.len() && n != PC && n != SP {
+                        fail!(EmulatorErrorKind::InvalidRegister { n })
+                    }
+                }
+                let value = $body as u64 & self.mask;
                 set!($to, value)
             }};
             (@assign [$to:ident]; $body:expr) => {{
-                let value = $body as u64;
+                let value = $body as u64 & self.mask;
                 setm!($to, value)
             }};
             (
@@ -291,26 +1005,13 @@ impl EmulatorState {
                     $(
 
                         insts!(@pat($name) $(($($ops)*))?) => {
-                            insts!(@assign $($assign)?; {
-                                $(insts!(@read $($ops)*);)?
-                                $body
-                            })
+                            insts!(@run ($($assign)?) ($($($ops)*)?) $body)
                         }
                     )*
                 }
             };
         }
 
-        macro_rules! branch {
-            ($dest:ident $(if $cond:expr)?) => {
-                match () {
-                    () $(if $cond)? => self.pc = $dest - 1,
-                    #[allow(unreachable_patterns)]
-                    () => (),
-                }
-            }
-        }
-
         macro_rules! SET {
             ($cond:expr) => {
                 if $cond {
@@ -321,39 +1022,115 @@ impl EmulatorState {
             };
         }
 
+        // handled after the match since inst still borrows the program
+        let mut port_err = None;
+
         insts! {
             NOP => {},
-            HLT => return StepResult::HLT,
+            HLT => {
+                if self.tracer.active() {
+                    self.tracer.commit();
+                }
+                return StepResult::HLT
+            },
 
             PSH(a) => {
-                if let Err(err) = self.stack.push(a) {
-                    self.error = err;
+                let (i, v) = match self.stack.top_slot() {
+                    Ok(slot) => slot,
+                    Err(err) => fail!(err),
+                };
+                if self.debugger.watches_memory() {
+                    self.debugger.mem_write((self.heap.len() + i) as u64, a);
                 }
+                journal!(Change::Stack(i, v));
+                trace!(Written::Mem((self.heap.len() + i) as u64, a));
+                let _ = self.stack.push(a);
             },
-            POP(a); a => {
-                match self.stack.pop() {
-                    Ok(v) => v,
-                    Err(err) => {
-                        self.error = err;
-                        a
-                    },
-                }
+            POP(a); a => match self.stack.pop() {
+                Ok(v) => {
+                    if self.debugger.watches_memory() {
+                        self.debugger.mem_read(self.heap.len() as u64 + self.stack.sp as u64);
+                    }
+                    v
+                },
+                Err(err) => fail!(err),
             },
             CAL(a: usize) => {
-                if let Err(err) = self.stack.push(self.pc as u64) {
-                    self.error = err;
+                if a > self.program.instructions.len() {
+                    fail!(EmulatorErrorKind::PcOutOfRange)
+                }
+                let (i, v) = match self.stack.top_slot() {
+                    Ok(slot) => slot,
+                    Err(err) => fail!(err),
+                };
+                if self.debugger.watches_memory() {
+                    self.debugger.mem_write((self.heap.len() + i) as u64, self.pc as u64 + 1);
+                }
+                journal!(Change::Stack(i, v));
+                trace!(Written::Mem((self.heap.len() + i) as u64, self.pc as u64 + 1));
+                // the return address is the instruction after the CAL
+                let _ = self.stack.push(self.pc as u64 + 1);
+                self.debugger.call();
+                if self.profiler.enabled() {
+                    self.profiler.call(a);
                 }
                 branch!(a)
             },
             RET => {
-                match self.stack.pop().map(|v| v as usize) {
-                    Ok(v) => branch!(v),
-                    Err(err) => self.error = err,
+                let v = match self.stack.peek() {
+                    Ok(v) => v as usize,
+                    Err(err) => fail!(err),
+                };
+                if v > self.program.instructions.len() {
+                    fail!(EmulatorErrorKind::PcOutOfRange)
+                }
+                let _ = self.stack.pop();
+                if self.debugger.watches_memory() {
+                    self.debugger.mem_read(self.heap.len() as u64 + self.stack.sp as u64);
+                }
+                self.debugger.ret();
+                if self.profiler.enabled() {
+                    self.profiler.ret();
                 }
+                branch!(v)
             },
 
-            IN(a, b); a => self.devices.in_port(b),
-            OUT(a, b) => self.devices.out(a, b),
+            IN(a, b); a => match match b == devices::IOPort::WAIT as u64 {
+                // the clock belongs to the emulator so %WAIT never reaches the devices either
+                true => Ok(Some(self.elapsed_ms() as u64)),
+                false => port_in!(b),
+            } {
+                Ok(Some(v)) => v,
+                Ok(None) => {
+                    // IN runs again once there is input
+                    self.debugger.retry();
+                    self.tracer.discard();
+                    if self.profiler.enabled() {
+                        self.profiler.uncount(pc);
+                    }
+                    return StepResult::Input
+                },
+                Err(err) if self.port_fatal(&err) => fail!(err),
+                Err(err) => {
+                    port_err = Some(err);
+                    0
+                },
+            },
+            // profiling regions need the emulator so they never reach the devices
+            OUT(a, b) => if a == devices::IOPort::PROFILE as u64 {
+                if self.profiler.enabled() {
+                    self.profiler.region(b);
+                }
+            } else if a == devices::IOPort::WAIT as u64 {
+                journal!(Change::Wait(self.wait_until));
+                // counted from the end of this instruction
+                self.wait_until = self.cycles + 1 + (b as u128 * self.clock_speed as u128 / 1000).min(u64::MAX as u128 / 2) as u64;
+            } else if let Err(err) = port_out!(a, b) {
+                if self.port_fatal(&err) {
+                    fail!(err)
+                }
+                port_err = Some(err);
+            },
 
             JMP(a: usize) => branch!(a),
             BRG(a: usize, b, c) => branch!(a if b > c),
@@ -365,8 +1142,8 @@ impl EmulatorState {
             BNE(a: usize, b, c) => branch!(a if b != c),
             BRZ(a: usize, b) => branch!(a if b == 0),
             BNZ(a: usize, b) => branch!(a if b != 0),
-            BRC(a: usize, b, c) => branch!(a if does_overflow(b, c)),
-            BNC(a: usize, b, c) => branch!(a if !does_overflow(b, c)),
+            BRC(a: usize, b, c) => branch!(a if does_overflow(b, c, self.mask)),
+            BNC(a: usize, b, c) => branch!(a if !does_overflow(b, c, self.mask)),
 
             SBRG(a: usize, b: i64, c: i64) => branch!(a if b > c),
             SBGE(a: usize, b: i64, c: i64) => branch!(a if b >= c),
@@ -382,21 +1159,21 @@ impl EmulatorState {
             STR(a, b); [a] => b,
             CPY(a, [b]); [a] => b,
             LOD(a, [b]); a => b,
-            LLOD(a, b, c); a => get_mem!(b + c),
-            LSTR(a, b, c) => set_mem!(a + b, c),
+            LLOD(a, b, c); a => get_mem!(b.wrapping_add(c) & self.mask),
+            LSTR(a, b, c) => set_mem!(a.wrapping_add(b) & self.mask, c),
 
-            ADD(a, b, c); a => b + c,
-            SUB(a, b, c); a => b - c,
-            INC(a, b); a => b + 1,
-            DEC(a, b); a => b - 1,
+            ADD(a, b, c); a => b.wrapping_add(c),
+            SUB(a, b, c); a => b.wrapping_sub(c),
+            INC(a, b); a => b.wrapping_add(1),
+            DEC(a, b); a => b.wrapping_sub(1),
 
             RSH(a, b); a => b >> 1,
             LSH(a, b); a => b << 1,
             SRS(a, b: i64); a => b >> 1,
 
-            BSR(a, b, c); a => b >> c,
-            BSL(a, b, c); a => b << c,
-            BSS(a, b: i64, c: i64); a => b >> c,
+            BSR(a, b, c); a => if c >= 64 {0} else {b >> c},
+            BSL(a, b, c); a => if c >= 64 {0} else {b << c},
+            BSS(a, b: i64, c); a => b >> c.min(63),
 
             OR(a, b, c); a => b | c,
             NOR(a, b, c); a => !(b | c),
@@ -406,18 +1183,18 @@ impl EmulatorState {
             XNOR(a, b, c); a => !(b ^ c),
 
             NOT(a, b); a => !b,
-            NEG(a, b: i64); a => -b,
-            ABS(a, b: i64); a => b.abs(),
+            NEG(a, b: i64); a => b.wrapping_neg(),
+            ABS(a, b: i64); a => b.wrapping_abs(),
 
-            MLT(a, b, c); a => b * c,
-            DIV(a, b, c); a => b / c,
-            SDIV(a, b: i64, c: i64); a => b / c,
-            MOD(a, b, c); a => b % c,
+            MLT(a, b, c); a => b.wrapping_mul(c),
+            DIV(a, b, c); a => match b.checked_div(c) {Some(v) => v, None => fail!(EmulatorErrorKind::DivisionByZero)},
+            SDIV(a, b: i64, c: i64); a => if c == 0 {fail!(EmulatorErrorKind::DivisionByZero)} else {b.wrapping_div(c)},
+            MOD(a, b, c); a => match b.checked_rem(c) {Some(v) => v, None => fail!(EmulatorErrorKind::DivisionByZero)},
 
             SETE(a, b, c); a => SET!(b == c),
             SETNE(a, b, c); a => SET!(b != c),
-            SETC(a, b, c); a => SET!(does_overflow(b, c)),
-            SETNC(a, b, c); a => SET!(!does_overflow(b, c)),
+            SETC(a, b, c); a => SET!(does_overflow(b, c, self.mask)),
+            SETNC(a, b, c); a => SET!(!does_overflow(b, c, self.mask)),
 
             SETG(a, b, c); a => SET!(b > c),
             SETGE(a, b, c); a => SET!(b >= c),
@@ -429,46 +1206,163 @@ impl EmulatorState {
             SSETLE(a, b: i64, c: i64); a => SET!(b <= c),
         }
 
+        if let Some(err) = port_err {
+            self.unsupported_port(err);
+        }
+
         self.pc += 1;
+        self.steps += 1;
+        self.cycles += 1;
+        if self.journal.enabled() {
+            self.journal.commit();
+        }
+        if self.tracer.active() {
+            self.tracer.commit();
+        }
 
-        match &self.error {
-            EmulatorError(Some(err)) => {
-                let mut out = String::new();
-                out_emu_err(&mut out, err, &self.program.debug.pc_to_line_start[self.pc-1].to_string(), "No Preview Yet!");
-                StepResult::Error
+        // errors already returned through fail!
+        for (reg, old) in watched_regs {
+            let new = self.regs.get((reg as usize).wrapping_sub(1)).copied().unwrap_or(0);
+            if new != old {
+                self.debugger.reg_changed(reg, old, new);
             }
-            EmulatorError(None) => StepResult::Continue,
         }
+        if self.debugger.after() {
+            return StepResult::Breakpoint;
+        }
+        StepResult::Continue
     }
 
     pub fn get_err(&mut self) -> Option<String> {
         match &self.error {
             EmulatorError(Some(err)) => {
                 let mut out = String::new();
-                out_emu_err(&mut out, err, &self.program.debug.pc_to_line_start[self.pc-1].to_string(), "No Preview Yet!");
+                let (lineno, line, col, span) = self.source_at(self.pc);
+                out_emu_err(&mut out, err, &lineno, line, col, span);
+                // fail! stops the instruction before it changes anything, so this is the state it ran in
+                out += &self.snapshot();
                 Some(out)
             }
             EmulatorError(None) => None,
         }
     }
 
+    // key codes are KeyboardEvent.keyCode, so letters and digits are their uppercase ascii
+    pub fn key_down(&mut self, key: u32) {
+        self.devices.input(InputEvent::KeyDown(key as u64));
+    }
+    pub fn key_up(&mut self, key: u32) {
+        self.devices.input(InputEvent::KeyUp(key as u64));
+    }
+    // in screen pixels
+    pub fn mouse_move(&mut self, x: i32, y: i32) {
+        self.devices.input(InputEvent::MouseMove(x as i64, y as i64));
+    }
+    pub fn mouse_buttons(&mut self, buttons: u32) {
+        self.devices.input(InputEvent::MouseButtons(buttons as u64));
+    }
+    pub fn mouse_wheel(&mut self, delta: i32) {
+        self.devices.input(InputEvent::Wheel(delta as i64));
+    }
+    pub fn set_gamepad(&mut self, index: u32, buttons: u32, axes: Vec<f64>) {
+        self.devices.input(InputEvent::Gamepad(index as usize, Some(Gamepad {buttons: buttons as u64, axes})));
+    }
+    pub fn remove_gamepad(&mut self, index: u32) {
+        self.devices.input(InputEvent::Gamepad(index as usize, None));
+    }
+    // replaces the host as the source of input, returns what was wrong with the script
+    pub fn load_input_script(&mut self, script: &str) -> Option<String> {
+        match InputScript::parse(script) {
+            Ok(script) => {
+                self.devices.script = Some(script);
+                None
+            },
+            Err(err) => Some(err),
+        }
+    }
+
+    pub fn set_screen_size(&mut self, width: usize, height: usize) {
+        self.devices.set_screen_size(width, height);
+    }
+
+    pub fn set_color_mode(&mut self, mode: devices::screen::ColorMode) {
+        self.devices.screen.set_mode(mode);
+    }
+    pub fn get_color_mode(&self) -> devices::screen::ColorMode {
+        self.devices.screen.mode()
+    }
+    // RGB colours used in palette mode
+    pub fn set_palette(&mut self, palette: Vec<u32>) {
+        self.devices.screen.set_palette(palette);
+    }
+
     pub fn get_screen(&self) -> crate::emulator::devices::screen::Screen {
         self.devices.screen.clone()
     }
+
+    // in words, what is already stored past the new size is dropped
+    pub fn set_storage_size(&mut self, words: usize) {
+        self.devices.set_storage_size(words);
+    }
+    // little endian words, as many bytes each as the word width needs
+    pub fn load_storage(&mut self, bytes: Vec<u8>) {
+        self.devices.storage.load_bytes(&bytes, self.bits);
+    }
+    pub fn export_storage(&self) -> Vec<u8> {
+        self.devices.storage.to_bytes(self.bits)
+    }
+
+    // files in the in memory file system %FILE uses
+    pub fn add_file(&mut self, name: &str, bytes: Vec<u8>) {
+        self.devices.files.insert(name, bytes);
+    }
+    pub fn remove_file(&mut self, name: &str) {
+        self.devices.files.remove(name);
+    }
+    pub fn get_file(&self, name: &str) -> Option<Vec<u8>> {
+        self.devices.files.get(name)
+    }
+    pub fn played_sound(&self) -> bool {
+        !self.devices.sound.events().is_empty()
+    }
+    // a wav of every note played so far, steps_per_second is how many cycles make a second
+    // which is get_clock_speed() unless the wav should play faster or slower than the program ran
+    pub fn render_sound(&self, steps_per_second: f64) -> Vec<u8> {
+        self.devices.sound.render_wav(steps_per_second, self.cycles)
+    }
+
+    // saves a file the program left open, returns false if that failed
+    pub fn close_files(&mut self) -> bool {
+        self.devices.files.close().is_ok()
+    }
 }
 
 #[allow(dead_code)]
 #[wasm_bindgen]
-pub fn emulate(src: String) -> Option<EmulatorState> {
+pub fn emulate(src: String, bits: Option<u64>, files: Option<MemorySources>) -> Option<EmulatorState> {
+    emulate_with_sources("main", src, bits, &mut files.unwrap_or_default())
+}
+
+// rust only since wasm has nowhere to write to
+impl EmulatorState {
+    pub fn trace_to(&mut self, writer: Box<dyn std::io::Write>) {
+        self.tracer.set_writer(writer);
+    }
+    // %FILE can only reach files inside these directories instead of the in memory file system
+    pub fn allow_dirs(&mut self, dirs: Vec<std::path::PathBuf>) {
+        self.devices.files.set_file_system(devices::file::FileSystem::Disk(dirs));
+    }
+}
+
+pub fn emulate_with_sources(name: &str, src: String, bits: Option<u64>, provider: &mut dyn SourceProvider) -> Option<EmulatorState> {
     // wifi died
-    let src = Rc::from(src);
     clear_text();
-    let toks = lexer::lex(&src);
+    let sources = Rc::new(SourceMap::load(name, src, provider));
 
     let Parser {
         ast: program, err, ..
-    } = ast::gen_ast(toks, src.clone());
-    jsprintln!("{}", err.to_string(&src));
+    } = ast::gen_ast(&sources, bits);
+    jsprintln!("{}", err.to_string(&sources));
     if err.has_error() {
         return None;
     }
@@ -480,13 +1374,12 @@ pub fn emulate(src: String) -> Option<EmulatorState> {
 }
 
 #[allow(dead_code)]
-pub fn silence_emulate(body: String) -> Result<EmulatorState, String> {
-    let src = Rc::from(body);
-    let toks = lexer::lex(&src);
-    let Parser {ast: program, err, ..} = ast::gen_ast(toks, src.clone());
+pub fn silence_emulate(name: &str, body: String, bits: Option<u64>, provider: &mut dyn SourceProvider) -> Result<EmulatorState, String> {
+    let sources = Rc::new(SourceMap::load(name, body, provider));
+    let Parser {ast: program, err, ..} = ast::gen_ast(&sources, bits);
 
     if err.has_error() {
-        return Err(err.to_string(&src));
+        return Err(err.to_string(&sources));
     }
 
     Ok(EmulatorState::new(program, DeviceHost::new()))
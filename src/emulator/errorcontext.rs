@@ -1,48 +1,51 @@
-use std::{fmt::{Debug, Display}, collections::HashMap};
+use std::fmt::{Debug, Display};
 
 use strum_macros::Display;
 
-use super::{lexer::{UToken}, ast::AstOp};
+use super::{lexer::{UToken}, ast::{AstOp, BitsCmp}, sources::SourceMap};
 
 #[allow(dead_code)]
 pub struct ErrorContext<'a> {
     errors: Vec<Error<'a>>,
     has_error: bool,
+    // call site of the macro expansion being parsed
+    pub expansion: Option<&'a str>,
 }
 
 #[allow(dead_code)]
 impl <'a> ErrorContext<'a> {
     pub fn new() -> Self {
-        Self { errors: Vec::new(), has_error: false }
+        Self { errors: Vec::new(), has_error: false, expansion: None }
     }
 
     pub fn error(&mut self, token: &UToken<'a>, kind: ErrorKind<'a>) {
-        self.errors.push(Error {kind, span: token.str, level: ErrorLevel::Error});
+        self.errors.push(Error {kind, span: token.str, level: ErrorLevel::Error, expanded_from: self.expansion});
         self.has_error = true;
     }
     pub fn warn(&mut self, token: &UToken<'a>, kind: ErrorKind<'a>) {
-        self.errors.push(Error {kind, span: token.str, level: ErrorLevel::Warning});
+        self.errors.push(Error {kind, span: token.str, level: ErrorLevel::Warning, expanded_from: self.expansion});
     }
     pub fn info(&mut self, token: &UToken<'a>, kind: ErrorKind<'a>) {
-        self.errors.push(Error {kind, span: token.str, level: ErrorLevel::Info});
+        self.errors.push(Error {kind, span: token.str, level: ErrorLevel::Info, expanded_from: self.expansion});
     }
     pub fn has_error(&self) -> bool {
         self.has_error
     }
 
-    pub fn to_string(&self, src: &str) -> String {
-        let mut linenos = HashMap::new();
-        for (i, line) in src.lines().enumerate() {
-            linenos.insert(line.as_ptr(), i+1);
-        }
-
+    pub fn to_string(&self, sources: &SourceMap) -> String {
+        let src = &sources.text;
         let mut output = String::new();
-        for error in &self.errors {
-            let (line, col) = line(src, error.span);
-            let lineno = linenos.get(&line.as_ptr()).map_or(0, |i|*i);
-            let lineno = format!("{} ", lineno);
+        let mut out = |error: &Error| {
+            let (line, col) = span_line(src, error.span);
+            let lineno = sources.lineno(line.as_ptr() as usize - src.as_ptr() as usize);
 
             crate::out_err(&mut output, error, &lineno, line, col);
+        };
+        for error in &self.errors {
+            out(error);
+            if let Some(call) = error.expanded_from {
+                out(&Error {kind: ErrorKind::InMacroExpansion, span: call, level: ErrorLevel::Info, expanded_from: None});
+            }
         }
         output
     }
@@ -67,7 +70,8 @@ pub enum ErrorLevel {
 pub struct Error<'a> {
     pub kind: ErrorKind<'a>,
     pub span: &'a str, // start and end of code that caused the error
-    pub level: ErrorLevel
+    pub level: ErrorLevel,
+    pub expanded_from: Option<&'a str>,
 }
 
 #[allow(dead_code)]
@@ -85,6 +89,14 @@ pub enum ErrorKind<'a> {
     EOFBeforeEndOfString,
     EOFBeforeEndOfChar,
     DuplicatedLabelName,
+    BitsMismatch{cmp: BitsCmp, value: u64, bits: u64},
+    UnsupportedBits(u64),
+    UndefinedMacro{name: &'a str, suggestion: Option<&'a str>},
+    MacroNoEnding,
+    MacroRecursion,
+    IncludeNotFound(String),
+    IncludeCycle(String),
+    InMacroExpansion,
     YoMamma
 }
 impl <'a> Display for ErrorKind<'a> {
@@ -102,6 +114,15 @@ impl <'a> Display for ErrorKind<'a> {
             ErrorKind::UndefinedLabel => write!(f, "Undefined label"),
             ErrorKind::DuplicatedLabelName => write!(f, "Duplicated label name"),
             ErrorKind::UnknownInstruction => write!(f, "Unknown instruction"),
+            ErrorKind::BitsMismatch { cmp, value, bits } => write!(f, "Word width of {} bits does not satisfy BITS {} {}", bits, cmp, value),
+            ErrorKind::UnsupportedBits(bits) => write!(f, "Unsupported word width of {} bits (must be 1 to 64)", bits),
+            ErrorKind::UndefinedMacro { name, suggestion: Some(suggestion) } => write!(f, "Undefined macro '{}', did you mean '{}'?", name, suggestion),
+            ErrorKind::UndefinedMacro { name, suggestion: None } => write!(f, "Undefined macro '{}'", name),
+            ErrorKind::MacroNoEnding => write!(f, "Missing '@END'"),
+            ErrorKind::MacroRecursion => write!(f, "Macro expanded too deeply or too many times"),
+            ErrorKind::IncludeNotFound(path) => write!(f, "Cannot find file \"{}\"", path),
+            ErrorKind::IncludeCycle(path) => write!(f, "\"{}\" is already being included", path),
+            ErrorKind::InMacroExpansion => write!(f, "In expansion of this macro"),
             ErrorKind::YoMamma => write!(f, "Token too large")
         }
     }
@@ -112,7 +133,7 @@ pub fn str_width(src: &str) -> usize {
     src.chars().count()
 }
 
-fn line<'a>(src: &'a str, span: &'a str) -> (&'a str, usize) {
+pub fn span_line<'a>(src: &'a str, span: &'a str) -> (&'a str, usize) {
     let mut offset = span.as_ptr() as usize - src.as_ptr() as usize;
     if offset >= src.len() {
         offset = src.len();
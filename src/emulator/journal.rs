@@ -0,0 +1,79 @@
+use std::collections::VecDeque;
+
+use super::devices::DeviceUndo;
+
+// something a step overwrote, holding the value from before
+#[derive(Debug, Clone)]
+pub enum Change {
+    Reg(usize, u64),
+    Heap(usize, u64),
+    Stack(usize, u64),
+    Device(DeviceUndo),
+    // the cycle OUT %WAIT waited until before
+    Wait(u64),
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub pc: usize,
+    pub sp: i64,
+    pub cycles: u64,
+    pub changes: Vec<Change>,
+}
+
+// undo history for stepping backwards, only the last `capacity` steps are kept
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+    current: Option<Entry>,
+}
+
+impl Journal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 0 turns the journal off
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+        if capacity == 0 {
+            self.current = None;
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn begin(&mut self, pc: usize, sp: i64, cycles: u64) {
+        self.current = Some(Entry {pc, sp, cycles, changes: Vec::new()});
+    }
+
+    pub fn record(&mut self, change: Change) {
+        if let Some(entry) = &mut self.current {
+            entry.changes.push(change);
+        }
+    }
+
+    pub fn commit(&mut self) {
+        if let Some(entry) = self.current.take() {
+            if self.entries.len() >= self.capacity {
+                self.entries.pop_front();
+            }
+            self.entries.push_back(entry);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Entry> {
+        self.entries.pop_back()
+    }
+}
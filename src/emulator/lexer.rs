@@ -73,7 +73,8 @@ pub fn lex(src: &str) -> Vec<Token<Kind>>{
                         Err(_err) => s.create(Error),
                     }
                 } else {
-                    s._while(char::is_alphanumeric); s.create(Port)
+                    // port names like G_SPECIAL have underscores
+                    s._while(|c| c.is_alphanumeric() || c == '_'); s.create(Port)
                 }
             },
             'a'..='z' | 'A'..='Z' => {
@@ -87,7 +88,7 @@ pub fn lex(src: &str) -> Vec<Token<Kind>>{
             '>' => {if s._if(|c|c=='=') {s.create(GE);} else {s.create(Error);}}
             '<' => {if s._if(|c|c=='=') {s.create(LE);} else {s.create(Error);}}
             '=' => {if s._if(|c|c=='=') {s.create(Eq);} else {s.create(Error);}}
-            '.' => {s._while(|c| !c.is_whitespace()); s.create(Label)},
+            '.' => {s._while(|c| !c.is_whitespace() && c != '[' && c != ']'); s.create(Label)},
             '/' => {if s._if(|c| c == '/') {
                 s._while(|c| c != '\n');
                 s.create(Comment);
@@ -4,4 +4,9 @@ pub mod lexer;
 pub mod errorcontext;
 pub mod highlighter;
 pub mod devices;
-use super::*;
\ No newline at end of file
+pub mod sources;
+pub mod debugger;
+pub mod journal;
+pub mod tracer;
+pub mod profiler;
+use super::*;
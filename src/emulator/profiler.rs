@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum ProfileReport {
+    Flat,
+    Graph,
+    Folded,
+}
+
+// a function entered with CAL or a region opened with OUT %PROFILE
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Frame {
+    Root,
+    Call(usize),
+    Region(u64),
+}
+
+#[derive(Debug)]
+struct Node {
+    frame: Frame,
+    parent: usize,
+    children: HashMap<Frame, usize>,
+    cycles: u64,
+}
+
+// every distinct call stack is a node so counting a step is just one add
+#[derive(Debug)]
+pub struct Profiler {
+    enabled: bool,
+    counts: Vec<u64>,
+    nodes: Vec<Node>,
+    current: usize,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {enabled: false, counts: Vec::new(), nodes: vec![Node {frame: Frame::Root, parent: 0, children: HashMap::new(), cycles: 0}], current: 0}
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn reset(&mut self) {
+        *self = Self {enabled: self.enabled, ..Self::default()};
+    }
+
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn count(&mut self, pc: usize) {
+        if pc >= self.counts.len() {
+            self.counts.resize(pc + 1, 0);
+        }
+        self.counts[pc] += 1;
+        self.nodes[self.current].cycles += 1;
+    }
+    // for an instruction that has to run again, like IN waiting for input
+    pub fn uncount(&mut self, pc: usize) {
+        self.counts[pc] -= 1;
+        self.nodes[self.current].cycles -= 1;
+    }
+
+    fn enter(&mut self, frame: Frame) {
+        let next = self.nodes.len();
+        let node = &mut self.nodes[self.current];
+        let child = *node.children.entry(frame).or_insert(next);
+        if child == next {
+            self.nodes.push(Node {frame, parent: self.current, children: HashMap::new(), cycles: 0});
+        }
+        self.current = child;
+    }
+
+    pub fn call(&mut self, target: usize) {
+        self.enter(Frame::Call(target));
+    }
+    // leaves the innermost call along with any regions still open inside it
+    pub fn ret(&mut self) {
+        let mut node = self.current;
+        while node != 0 {
+            if let Frame::Call(_) = self.nodes[node].frame {
+                self.current = self.nodes[node].parent;
+                return;
+            }
+            node = self.nodes[node].parent;
+        }
+    }
+    // the same value closes the region again, so OUT %PROFILE works as a toggle
+    pub fn region(&mut self, id: u64) {
+        let mut node = self.current;
+        while node != 0 {
+            match self.nodes[node].frame {
+                Frame::Region(open) if open == id => {
+                    self.current = self.nodes[node].parent;
+                    return;
+                },
+                // regions dont reach outside the call they were opened in
+                Frame::Call(_) => break,
+                _ => node = self.nodes[node].parent,
+            }
+        }
+        self.enter(Frame::Region(id));
+    }
+
+    // children always come after their parent so one pass backwards adds everything up
+    fn totals(&self) -> Vec<u64> {
+        let mut totals: Vec<u64> = self.nodes.iter().map(|node| node.cycles).collect();
+        for i in (1..self.nodes.len()).rev() {
+            totals[self.nodes[i].parent] += totals[i];
+        }
+        totals
+    }
+
+    fn path(&self, mut node: usize) -> Vec<usize> {
+        let mut path = vec![node];
+        while node != 0 {
+            node = self.nodes[node].parent;
+            path.push(node);
+        }
+        path.reverse();
+        path
+    }
+
+    // self and total cycles per name, recursion only counts the outermost call towards the total
+    pub fn functions(&self, name: &dyn Fn(Frame) -> String) -> Vec<(String, u64, u64)> {
+        let names: Vec<String> = self.nodes.iter().map(|node| name(node.frame)).collect();
+        let totals = self.totals();
+        let mut order = Vec::new();
+        let mut table: HashMap<&str, (u64, u64)> = HashMap::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            let entry = table.entry(&names[i]).or_insert_with(|| {
+                order.push(names[i].as_str());
+                (0, 0)
+            });
+            entry.0 += node.cycles;
+            let path = self.path(i);
+            let outermost = !path[..path.len() - 1].iter().any(|&p| names[p] == names[i]);
+            if outermost {
+                entry.1 += totals[i];
+            }
+        }
+        let mut out: Vec<(String, u64, u64)> = order.into_iter().map(|n| (n.to_string(), table[n].0, table[n].1)).collect();
+        out.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+        out
+    }
+
+    pub fn call_graph(&self, name: &dyn Fn(Frame) -> String) -> String {
+        let totals = self.totals();
+        let mut out = format!("{:>10} {:>10}  name\n", "total", "self");
+        let mut todo = vec![(0, 0)];
+        while let Some((node, depth)) = todo.pop() {
+            let n = &self.nodes[node];
+            out.push_str(&format!("{:>10} {:>10}  {}{}\n", totals[node], n.cycles, "  ".repeat(depth), name(n.frame)));
+            // biggest child last so it comes off the stack first
+            let mut children: Vec<usize> = n.children.values().copied().collect();
+            children.sort_by(|&a, &b| totals[a].cmp(&totals[b]).then(b.cmp(&a)));
+            todo.extend(children.into_iter().map(|child| (child, depth + 1)));
+        }
+        out
+    }
+
+    // one line per call stack, the format flamegraph.pl and friends read
+    pub fn folded(&self, name: &dyn Fn(Frame) -> String) -> String {
+        let mut out = String::new();
+        for (i, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let stack: Vec<String> = self.path(i).into_iter().map(|p| name(self.nodes[p].frame).replace([';', ' '], "_")).collect();
+            out.push_str(&format!("{} {}\n", stack.join(";"), node.cycles));
+        }
+        out
+    }
+}
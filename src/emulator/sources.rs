@@ -0,0 +1,170 @@
+use std::{collections::HashMap, ops::Range, rc::Rc};
+
+use wasm_bindgen::prelude::*;
+
+use super::lexer::{self, Kind};
+
+// where @include gets its files from
+pub trait SourceProvider {
+    fn read(&mut self, path: &str) -> Option<String>;
+}
+
+// reads includes relative to the main file's directory, used by the cli
+#[allow(dead_code)]
+pub struct FileSources {
+    root: std::path::PathBuf,
+}
+
+#[allow(dead_code)]
+impl FileSources {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl SourceProvider for FileSources {
+    fn read(&mut self, path: &str) -> Option<String> {
+        std::fs::read_to_string(self.root.join(path)).ok()
+    }
+}
+
+// files kept in memory, used by wasm and the bot
+#[wasm_bindgen]
+#[derive(Debug, Clone, Default)]
+pub struct MemorySources {
+    files: HashMap<String, String>,
+}
+
+#[allow(dead_code)]
+#[wasm_bindgen]
+impl MemorySources {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert(&mut self, path: String, src: String) {
+        self.files.insert(path, src);
+    }
+}
+
+impl SourceProvider for MemorySources {
+    fn read(&mut self, path: &str) -> Option<String> {
+        self.files.get(path).cloned()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub range: Range<usize>,
+}
+
+// every file a program uses, stored one after another in a single string
+// so tokens from all of them can borrow from the same place
+#[derive(Debug)]
+pub struct SourceMap {
+    pub text: Rc<str>,
+    pub files: Vec<SourceFile>,
+    // byte offset every line of text starts at, so locate doesnt have to count newlines
+    line_starts: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn load(name: &str, src: String, provider: &mut dyn SourceProvider) -> Self {
+        let mut text = src;
+        let mut files = vec![SourceFile {name: name.to_string(), range: 0..text.len()}];
+        let mut pending: Vec<String> = includes(&text).iter().map(|path| include_path("", path)).collect();
+        while let Some(path) = pending.pop() {
+            if files.iter().any(|file| file.name == path) {
+                continue;
+            }
+            // missing files are reported by the parser at the @include
+            let Some(src) = provider.read(&path) else {continue};
+            pending.extend(includes(&src).iter().map(|inc| include_path(&path, inc)));
+            text.push('\n');
+            let start = text.len();
+            text += &src;
+            files.push(SourceFile {name: path, range: start..text.len()});
+        }
+        let line_starts = std::iter::once(0).chain(text.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        Self {text: Rc::from(text), files, line_starts}
+    }
+
+    pub fn file(&self, name: &str) -> Option<usize> {
+        self.files.iter().position(|file| file.name == name)
+    }
+
+    // name an @include in file from gets loaded under
+    pub fn include_name(&self, from: usize, path: &str) -> String {
+        match from {
+            0 => include_path("", path),
+            _ => include_path(&self.files[from].name, path),
+        }
+    }
+
+    pub fn src(&self, file: usize) -> &str {
+        &self.text[self.files[file].range.clone()]
+    }
+
+    // file index and 1 based line of a byte offset into text
+    pub fn locate(&self, offset: usize) -> (usize, usize) {
+        let file = self.files.iter().rposition(|file| file.range.start <= offset).unwrap_or(0);
+        let first = self.line_starts.partition_point(|&start| start <= self.files[file].range.start);
+        let line = self.line_starts.partition_point(|&start| start <= offset.min(self.text.len()));
+        (file, line - first + 1)
+    }
+
+    // line number as shown in diagnostics, the main file leaves out its name
+    pub fn lineno(&self, offset: usize) -> String {
+        match self.locate(offset) {
+            (0, line) => format!("{} ", line),
+            (file, line) => format!("{}:{} ", self.files[file].name, line),
+        }
+    }
+}
+
+// includes are relative to the file they are in, names stay relative to the main file's directory.
+// . and .. are folded away so a file reached two ways is only loaded once
+fn include_path(includer: &str, path: &str) -> String {
+    if path.starts_with('/') {
+        return path.to_string();
+    }
+    let mut parts: Vec<&str> = includer.split('/').collect();
+    parts.pop();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {},
+            ".." if parts.last().is_some_and(|last| *last != "..") => {parts.pop();},
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+// paths of the @include directives in a file
+fn includes(src: &str) -> Vec<String> {
+    let toks = lexer::lex(src);
+    let mut paths = Vec::new();
+    let mut i = 0;
+    while i < toks.len() {
+        if toks[i].kind == Kind::Macro && toks[i].str.eq_ignore_ascii_case("@include") {
+            i += 1;
+            while i < toks.len() && toks[i].kind == Kind::White {i += 1;}
+            if i < toks.len() && toks[i].kind == Kind::String {
+                let mut path = String::new();
+                i += 1;
+                while i < toks.len() {
+                    match toks[i].kind {
+                        Kind::Text => path += toks[i].str,
+                        Kind::Escape(c) => path.push(c),
+                        _ => break,
+                    }
+                    i += 1;
+                }
+                paths.push(path);
+            }
+        }
+        i += 1;
+    }
+    paths
+}
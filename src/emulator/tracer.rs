@@ -0,0 +1,208 @@
+use std::{collections::HashSet, io::Write, ops::Range};
+
+use wasm_bindgen::prelude::*;
+
+use super::ast::InstClass;
+
+// binary records are little endian:
+//   u32 pc, u32 line, u8 reads, u8 writes,
+//   then a u64 per read, then per write a u8 kind (0 register, 1 memory), u64 target and u64 value
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum_macros::EnumString)]
+#[strum(ascii_case_insensitive)]
+pub enum TraceFormat {
+    Text,
+    Json,
+    Binary,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Written {
+    Reg(u64, u64),
+    Mem(u64, u64),
+}
+
+// one executed instruction
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub pc: usize,
+    pub file: String,
+    pub line: usize,
+    pub lineno: String,
+    pub inst: String,
+    pub reads: Vec<u64>,
+    pub writes: Vec<Written>,
+}
+
+#[derive(Default)]
+pub struct Tracer {
+    format: Option<TraceFormat>,
+    pcs: Option<Range<usize>>,
+    classes: HashSet<InstClass>,
+    buffer: Vec<u8>,
+    // when set records go here instead of the buffer
+    writer: Option<Box<dyn Write>>,
+    current: Option<Record>,
+}
+
+impl std::fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tracer").field("format", &self.format).field("pcs", &self.pcs).field("classes", &self.classes).finish()
+    }
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&mut self, format: TraceFormat) {
+        self.format = Some(format);
+    }
+    pub fn stop(&mut self) {
+        self.format = None;
+        self.current = None;
+        if let Some(writer) = &mut self.writer {
+            let _ = writer.flush();
+        }
+    }
+    pub fn set_writer(&mut self, writer: Box<dyn Write>) {
+        self.writer = Some(writer);
+    }
+    pub fn set_pcs(&mut self, pcs: Option<Range<usize>>) {
+        self.pcs = pcs;
+    }
+    // no classes means every class is traced
+    pub fn set_class(&mut self, class: InstClass, traced: bool) {
+        if traced {self.classes.insert(class);} else {self.classes.remove(&class);}
+    }
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.format.is_some()
+    }
+    #[inline]
+    pub fn active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn wants(&self, pc: usize, class: InstClass) -> bool {
+        self.enabled()
+            && self.pcs.as_ref().is_none_or(|pcs| pcs.contains(&pc))
+            && (self.classes.is_empty() || self.classes.contains(&class))
+    }
+
+    pub fn begin(&mut self, record: Record) {
+        self.current = Some(record);
+    }
+    pub fn read(&mut self, value: u64) {
+        if let Some(record) = &mut self.current {
+            record.reads.push(value);
+        }
+    }
+    pub fn write(&mut self, written: Written) {
+        if let Some(record) = &mut self.current {
+            record.writes.push(written);
+        }
+    }
+    // drops the record of an instruction that has to run again, like IN waiting for input
+    pub fn discard(&mut self) {
+        self.current = None;
+    }
+
+    pub fn commit(&mut self) {
+        let (Some(format), Some(record)) = (self.format, self.current.take()) else {return};
+        let out = match format {
+            TraceFormat::Text => text(&record).into_bytes(),
+            TraceFormat::Json => json(&record).into_bytes(),
+            TraceFormat::Binary => binary(&record),
+        };
+        match &mut self.writer {
+            Some(writer) => {
+                // a broken trace file shouldnt stop the program
+                if writer.write_all(&out).is_err() {
+                    self.writer = None;
+                }
+            },
+            None => self.buffer.extend_from_slice(&out),
+        }
+    }
+}
+
+fn target(written: &Written) -> String {
+    use super::emulator::{PC, SP};
+    match *written {
+        Written::Reg(PC, _) => "PC".to_string(),
+        Written::Reg(SP, _) => "SP".to_string(),
+        Written::Reg(reg, _) => format!("r{}", reg),
+        Written::Mem(addr, _) => format!("M{}", addr),
+    }
+}
+
+fn text(record: &Record) -> String {
+    let mut out = format!("{:>5} {:>6}| {:<24}", record.pc, record.lineno, record.inst);
+    if !record.reads.is_empty() {
+        let reads: Vec<String> = record.reads.iter().map(|v| v.to_string()).collect();
+        out += &format!(" <- {}", reads.join(", "));
+    }
+    if !record.writes.is_empty() {
+        let writes: Vec<String> = record.writes.iter().map(|w| match w {
+            Written::Reg(_, v) | Written::Mem(_, v) => format!("{}={}", target(w), v),
+        }).collect();
+        out += &format!(" => {}", writes.join(", "));
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+fn json_str(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out += "\\\"",
+            '\\' => out += "\\\\",
+            c if (c as u32) < 0x20 => out += &format!("\\u{:04x}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json(record: &Record) -> String {
+    let reads: Vec<String> = record.reads.iter().map(|v| v.to_string()).collect();
+    let writes: Vec<String> = record.writes.iter().map(|w| match w {
+        Written::Reg(_, v) => format!("{{\"reg\":{},\"value\":{}}}", json_str(&target(w)), v),
+        Written::Mem(addr, v) => format!("{{\"mem\":{},\"value\":{}}}", addr, v),
+    }).collect();
+    format!(
+        "{{\"pc\":{},\"file\":{},\"line\":{},\"inst\":{},\"reads\":[{}],\"writes\":[{}]}}\n",
+        record.pc, json_str(&record.file), record.line, json_str(&record.inst), reads.join(","), writes.join(",")
+    )
+}
+
+fn binary(record: &Record) -> Vec<u8> {
+    let mut out = Vec::with_capacity(10 + record.reads.len() * 8 + record.writes.len() * 17);
+    out.extend_from_slice(&(record.pc as u32).to_le_bytes());
+    out.extend_from_slice(&(record.line as u32).to_le_bytes());
+    out.push(record.reads.len().min(255) as u8);
+    out.push(record.writes.len().min(255) as u8);
+    for v in record.reads.iter().take(255) {
+        out.extend_from_slice(&v.to_le_bytes());
+    }
+    for w in record.writes.iter().take(255) {
+        let (kind, target, value) = match *w {
+            Written::Reg(reg, v) => (0u8, reg, v),
+            Written::Mem(addr, v) => (1u8, addr, v),
+        };
+        out.push(kind);
+        out.extend_from_slice(&target.to_le_bytes());
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out
+}
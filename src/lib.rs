@@ -22,6 +22,8 @@ extern {
 
     pub fn out_screen(width: usize, height: usize, pixels: &[u32]);
     pub fn clear_screen();
+
+    pub fn out_sound(cycle: f64, note: u32, instrument: u32);
 }
 
 #[wasm_bindgen]
@@ -66,31 +68,31 @@ pub fn out_err(out: &mut String, error: &emulator::errorcontext::Error, lineno:
     ).unwrap();
 }
 
-pub fn out_emu_err(out: &mut String, error: &emulator::emulator::EmulatorErrorKind, lineno: &String, line: &str) {
+pub fn out_emu_err(out: &mut String, error: &emulator::emulator::EmulatorErrorKind, lineno: &String, line: &str, col: usize, span: &str) {
     use std::fmt::Write;
     use crate::emulator::errorcontext::*;
     writeln!(out, "<span class=\"error\">Error: {}</span>", error).unwrap();
     writeln!(out, "{}| {}", 
         lineno, html_escape::encode_text(&line.split_at(get_indent_level(line)).1.replace("\t", " "))
     ).unwrap();
+    writeln!(out, "{}| {}{}",
+        " ".repeat(str_width(lineno)),
+        &" ".repeat(col.saturating_sub(get_indent_level(line))),
+        &"^".repeat(str_width(span).max(1))
+    ).unwrap();
 }
 
-static mut RAND_SEED: u64 = 0;
-
-pub fn rand() -> u64 {
-    unsafe {
-        let mut x = RAND_SEED;
-        if x == 0 {x = now() as u64;}
-        x ^= x << 13;
-        x ^= x >> 7;
-        x ^= x << 17;
-        RAND_SEED = x;
-        x
-    }
+pub fn out_emu_warn(out: &mut String, error: &emulator::emulator::EmulatorErrorKind, lineno: &String, line: &str, col: usize, span: &str) {
+    use std::fmt::Write;
+    use crate::emulator::errorcontext::*;
+    writeln!(out, "<span class=\"warning\">Warning: {}</span>", error).unwrap();
+    writeln!(out, "{}| {}", 
+        lineno, html_escape::encode_text(&line.split_at(get_indent_level(line)).1.replace("\t", " "))
+    ).unwrap();
+    writeln!(out, "{}| {}{}",
+        " ".repeat(str_width(lineno)),
+        &" ".repeat(col.saturating_sub(get_indent_level(line))),
+        &"^".repeat(str_width(span).max(1))
+    ).unwrap();
 }
 
-pub fn srand(seed: u64) {
-    unsafe {
-        RAND_SEED = seed;
-    }
-}
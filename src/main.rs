@@ -4,6 +4,65 @@ mod emulator;
 #[cfg(feature = "bot")]
 mod discord_bot;
 
+// reads debugger commands until the program should keep running, false means quit
+#[cfg(not(feature = "bot"))]
+fn debug_prompt(emu: &mut emulator::emulator::EmulatorState) -> bool {
+    use std::io::Write;
+    loop {
+        print!("(debug) ");
+        std::io::stdout().flush().unwrap();
+        let line = in_text();
+        if line.is_empty() {
+            return false;
+        }
+        let mut words = line.split_whitespace();
+        let cmd = words.next().unwrap_or("");
+        let arg = words.next().and_then(|v| v.parse::<u64>().ok());
+        let len = words.next().and_then(|v| v.parse::<usize>().ok()).unwrap_or(16);
+        match (cmd, arg) {
+            ("c" | "continue" | "", _) => return true,
+            ("s" | "step", _) => {emu.step_in(); return true},
+            ("n" | "next", _) => {emu.step_over(); return true},
+            ("f" | "finish", _) => {emu.step_out(); return true},
+            ("q" | "quit", _) => return false,
+            ("b" | "break", Some(line)) => match emu.add_line_breakpoint(line as usize) {
+                Some(line) => println!("Breakpoint at line {}", line),
+                None => println!("No code at or after line {}", line),
+            },
+            ("bp" | "break-pc", Some(pc)) => emu.add_breakpoint(pc as usize),
+            ("d" | "delete", Some(line)) => emu.remove_line_breakpoint(line as usize),
+            ("dp" | "delete-pc", Some(pc)) => emu.remove_breakpoint(pc as usize),
+            ("w" | "watch", Some(addr)) => emu.watch_memory(addr, false, true),
+            ("rw" | "watch-read", Some(addr)) => emu.watch_memory(addr, true, true),
+            ("uw" | "unwatch", Some(addr)) => emu.watch_memory(addr, false, false),
+            ("wr" | "watch-reg", Some(reg)) => emu.watch_register(reg, true),
+            ("ur" | "unwatch-reg", Some(reg)) => emu.watch_register(reg, false),
+            ("i" | "info", _) => println!("{}", emu.get_break_reason().unwrap_or_else(|| emu.get_location())),
+            ("x" | "mem", Some(addr)) => {
+                let words: Vec<String> = emu.get_memory(addr as usize, len).iter().map(|v| v.to_string()).collect();
+                println!("M{}: {}", addr, words.join(" "));
+            },
+            ("stack", _) => println!("{:?}", emu.get_stack()),
+            ("dis" | "disassemble", pc) => {
+                let pc = pc.map_or(emu.get_pc(), |pc| pc as usize);
+                for pc in pc..pc + len.min(16) {
+                    let Some(inst) = emu.disassemble(pc) else {break};
+                    println!("{}{:>5}: {}", if pc == emu.get_pc() {">"} else {" "}, pc, inst);
+                }
+            },
+            ("back", _) => match emu.step_back() {
+                true => println!("{}", emu.get_location()),
+                false => println!("Nothing to undo, start with --journal N to record steps"),
+            },
+            ("back-to", Some(pc)) => match emu.run_back_to(pc as usize) {
+                true => println!("{}", emu.get_location()),
+                false => println!("pc {} is not in the journal\n{}", pc, emu.get_location()),
+            },
+            _ => println!("Commands: continue, step, next, finish, back, back-to PC, quit, info, x ADDR [LEN], stack, dis [PC], break LINE, break-pc PC, delete LINE, delete-pc PC, watch ADDR, watch-read ADDR, unwatch ADDR, watch-reg N, unwatch-reg N"),
+        }
+    }
+}
+
 fn main() {
     #[cfg(not(feature = "bot"))] {
         let args: Vec<String> = std::env::args().collect();
@@ -12,12 +71,206 @@ fn main() {
             return;
         }
         let fname = &args[1];
+        let mut bits = None;
+        let mut debug = Vec::new();
+        let mut port_policy = emulator::emulator::PortPolicy::Error;
+        let mut trace: Option<&str> = None;
+        let mut trace_format = emulator::tracer::TraceFormat::Text;
+        let mut trace_pcs = None;
+        let mut trace_classes = Vec::new();
+        let mut profile = None;
+        let mut screen = None;
+        let mut color_mode = None;
+        let mut input_script = None;
+        let mut storage: Option<&str> = None;
+        let mut storage_size = None;
+        let mut allowed_dirs = Vec::new();
+        let mut sound: Option<&str> = None;
+        let mut sound_speed = None;
+        let mut clock_speed = None;
+        let mut seed = None;
+        let mut fixed_point = None;
+        let mut profile_out: Option<&str> = None;
+        let mut rest = args.iter().skip(2);
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--bits" => {
+                    match rest.next().map(|v| v.parse::<u64>()) {
+                        Some(Ok(v)) if (1..=64).contains(&v) => bits = Some(v),
+                        _ => {
+                            println!("\x1b[1;31mError: --bits expects a word width from 1 to 64.\x1b[0;0m");
+                            return;
+                        }
+                    }
+                },
+                "--break" | "--break-pc" | "--watch" | "--watch-read" | "--watch-reg" | "--journal" => {
+                    match rest.next().map(|v| v.parse::<u64>()) {
+                        Some(Ok(v)) => debug.push((arg.as_str(), v)),
+                        _ => {
+                            println!("\x1b[1;31mError: {} expects a number.\x1b[0;0m", arg);
+                            return;
+                        }
+                    }
+                },
+                "--debug" => debug.push(("--debug", 0)),
+                "--trace" => match rest.next() {
+                    Some(path) => trace = Some(path),
+                    None => {
+                        println!("\x1b[1;31mError: --trace expects a file, or - for stdout.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--trace-format" => match rest.next().map(|v| v.parse()) {
+                    Some(Ok(format)) => trace_format = format,
+                    _ => {
+                        println!("\x1b[1;31mError: --trace-format expects one of text, json or binary.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--trace-pcs" => match rest.next().and_then(|v| v.split_once('-')).map(|(a, b)| (a.parse::<usize>(), b.parse::<usize>())) {
+                    Some((Ok(start), Ok(end))) => trace_pcs = Some((start, end)),
+                    _ => {
+                        println!("\x1b[1;31mError: --trace-pcs expects a range like 10-20.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--screen" => match rest.next().and_then(|v| v.split_once('x')).map(|(w, h)| (w.parse::<usize>(), h.parse::<usize>())) {
+                    Some((Ok(w), Ok(h))) if w > 0 && h > 0 => screen = Some((w, h)),
+                    _ => {
+                        println!("\x1b[1;31mError: --screen expects a size like 64x48.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--color-mode" => match rest.next().map(|v| v.parse::<emulator::devices::screen::ColorMode>()) {
+                    Some(Ok(mode)) => color_mode = Some(mode),
+                    _ => {
+                        println!("\x1b[1;31mError: --color-mode expects one of rgb888, rgb565, rgb332, grayscale or palette.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--input-script" => match rest.next().map(std::fs::read_to_string) {
+                    Some(Ok(script)) => input_script = Some(script),
+                    Some(Err(err)) => {
+                        println!("\x1b[1;31mError: Cannot read input script (Returns error \"{}\")\x1b[0;0m", err);
+                        return;
+                    },
+                    None => {
+                        println!("\x1b[1;31mError: --input-script expects a file.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--storage" => match rest.next() {
+                    Some(path) => storage = Some(path),
+                    None => {
+                        println!("\x1b[1;31mError: --storage expects a file.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--storage-size" => match rest.next().map(|v| v.parse::<usize>()) {
+                    Some(Ok(words)) => storage_size = Some(words),
+                    _ => {
+                        println!("\x1b[1;31mError: --storage-size expects a number of words.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--allow-dir" => match rest.next().map(std::fs::canonicalize) {
+                    Some(Ok(dir)) => allowed_dirs.push(dir),
+                    Some(Err(err)) => {
+                        println!("\x1b[1;31mError: Cannot use directory for --allow-dir (Returns error \"{}\")\x1b[0;0m", err);
+                        return;
+                    },
+                    None => {
+                        println!("\x1b[1;31mError: --allow-dir expects a directory.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--sound" => match rest.next() {
+                    Some(path) => sound = Some(path),
+                    None => {
+                        println!("\x1b[1;31mError: --sound expects a wav file.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--fixed-point" => match rest.next().map(|v| v.parse::<u32>()) {
+                    Some(Ok(point)) => fixed_point = Some(point),
+                    _ => {
+                        println!("\x1b[1;31mError: --fixed-point expects how many bits come after the binary point.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--seed" => match rest.next().map(|v| v.parse::<u64>()) {
+                    Some(Ok(v)) => seed = Some(v),
+                    _ => {
+                        println!("\x1b[1;31mError: --seed expects a number.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--sound-speed" => match rest.next().map(|v| v.parse::<f64>()) {
+                    Some(Ok(speed)) if speed > 0.0 => sound_speed = Some(speed),
+                    _ => {
+                        println!("\x1b[1;31mError: --sound-speed expects how many instructions make a second.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--clock" => match rest.next().map(|v| v.parse::<f64>()) {
+                    Some(Ok(hz)) if hz >= 1.0 => clock_speed = Some(hz),
+                    _ => {
+                        println!("\x1b[1;31mError: --clock expects a clock speed in Hz.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--profile" => match rest.next().map(|v| v.parse::<emulator::profiler::ProfileReport>()) {
+                    Some(Ok(report)) => profile = Some(report),
+                    _ => {
+                        println!("\x1b[1;31mError: --profile expects one of flat, graph or folded.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--profile-out" => match rest.next() {
+                    Some(path) => profile_out = Some(path),
+                    None => {
+                        println!("\x1b[1;31mError: --profile-out expects a file.\x1b[0;0m");
+                        return;
+                    }
+                },
+                "--trace-class" => {
+                    for class in rest.next().map(|v| v.as_str()).unwrap_or("").split(',') {
+                        match class.parse::<emulator::ast::InstClass>() {
+                            Ok(class) => trace_classes.push(class),
+                            Err(_) => {
+                                println!("\x1b[1;31mError: Unknown instruction class {}, expected arithmetic, bitwise, compare, branch, memory, stack, io or other.\x1b[0;0m", class);
+                                return;
+                            }
+                        }
+                    }
+                },
+                "--ports" => {
+                    use emulator::emulator::PortPolicy;
+                    port_policy = match rest.next().map(|v| v.as_str()) {
+                        Some("error") => PortPolicy::Error,
+                        Some("warn") => PortPolicy::WarnOnce,
+                        Some("log") => PortPolicy::Log,
+                        _ => {
+                            println!("\x1b[1;31mError: --ports expects one of error, warn or log.\x1b[0;0m");
+                            return;
+                        }
+                    }
+                },
+                _ => {
+                    println!("\x1b[1;31mError: Unknown argument {}.\x1b[0;0m", arg);
+                    return;
+                }
+            }
+        }
         let src = std::fs::read_to_string(fname);
         match &src {Err(err) => {
             println!("\x1b[1;31mError: Cannot read file {} (Returns error \"{}\")\x1b[0;0m", fname, err);
             return;
         }, _ => ()}
-        let emu = emulator::emulator::emulate(src.unwrap());
+        // includes are looked up next to the main file
+        let dir = std::path::Path::new(fname).parent().unwrap_or(std::path::Path::new("."));
+        let mut files = emulator::sources::FileSources::new(dir);
+        let emu = emulator::emulator::emulate_with_sources(fname, src.unwrap(), bits, &mut files);
         match emu {
             None => {
                 println!("\x1b[1;31mError: Compilation failed\x1b[0;0m");
@@ -25,7 +278,125 @@ fn main() {
             }
             _ => (),
         } 
-        println!("{:?}", emu.unwrap().run());
+        let mut emu = emu.unwrap();
+        emu.set_port_policy(port_policy);
+        if let Some(path) = trace {
+            let writer: Box<dyn std::io::Write> = match path {
+                "-" => Box::new(std::io::stdout()),
+                _ => match std::fs::File::create(path) {
+                    Ok(file) => Box::new(std::io::BufWriter::new(file)),
+                    Err(err) => {
+                        println!("\x1b[1;31mError: Cannot create trace file {} (Returns error \"{}\")\x1b[0;0m", path, err);
+                        return;
+                    }
+                },
+            };
+            emu.trace_to(writer);
+            emu.start_trace(trace_format);
+            if let Some((start, end)) = trace_pcs {
+                emu.trace_pcs(start, end);
+            }
+            for class in trace_classes {
+                emu.trace_class(class, true);
+            }
+        }
+        emu.set_profiling(profile.is_some());
+        if let Some(hz) = clock_speed {
+            emu.set_clock_speed(hz);
+        }
+        if let Some(seed) = seed {
+            emu.set_seed(seed);
+        }
+        if let Some(point) = fixed_point {
+            emu.set_fixed_point(point);
+        }
+        if let Some((width, height)) = screen {
+            emu.set_screen_size(width, height);
+        }
+        if let Some(mode) = color_mode {
+            emu.set_color_mode(mode);
+        }
+        if let Some(words) = storage_size {
+            emu.set_storage_size(words);
+        }
+        // a storage file that doesnt exist yet starts out empty
+        if let Some(path) = storage {
+            match std::fs::read(path) {
+                Ok(bytes) => emu.load_storage(bytes),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+                Err(err) => {
+                    println!("\x1b[1;31mError: Cannot read storage from {} (Returns error \"{}\")\x1b[0;0m", path, err);
+                    return;
+                },
+            }
+        }
+        // without any directories %FILE cant touch the disk at all
+        emu.allow_dirs(allowed_dirs);
+        if let Some(err) = input_script.and_then(|script| emu.load_input_script(&script)) {
+            println!("\x1b[1;31mError: {}\x1b[0;0m", err);
+            return;
+        }
+        for (arg, v) in debug {
+            match arg {
+                "--break" => {emu.add_line_breakpoint(v as usize);},
+                "--break-pc" => emu.add_breakpoint(v as usize),
+                "--watch" => emu.watch_memory(v, false, true),
+                "--watch-read" => emu.watch_memory(v, true, true),
+                "--watch-reg" => emu.watch_register(v, true),
+                "--journal" => emu.set_journal_capacity(v as usize),
+                _ => emu.pause(),
+            }
+        }
+        loop {
+            use emulator::emulator::StepResult;
+            let result = emu.run();
+            // run already printed everything so far
+            emu.clear_output();
+            match result {
+                StepResult::Input => {
+                    let text = in_text();
+                    if text.is_empty() {
+                        println!("{:?}", result);
+                        break;
+                    }
+                    emu.push_input(&text);
+                },
+                StepResult::Breakpoint if debug_prompt(&mut emu) => {},
+                // the cli runs as fast as it can but still waits for real
+                StepResult::Waiting => {
+                    std::thread::sleep(std::time::Duration::from_secs_f64(emu.wait_remaining_ms() / 1000.0));
+                    emu.skip_wait();
+                },
+                _ => {
+                    println!("{:?}", result);
+                    break;
+                },
+            }
+        }
+        emu.stop_trace();
+        if let Some(path) = sound {
+            // the wav follows the clock unless --sound-speed says otherwise
+            if let Err(err) = std::fs::write(path, emu.render_sound(sound_speed.unwrap_or(emu.get_clock_speed()))) {
+                println!("\x1b[1;31mError: Cannot write sound to {} (Returns error \"{}\")\x1b[0;0m", path, err);
+            }
+        }
+        if !emu.close_files() {
+            println!("\x1b[1;31mError: Cannot write the file the program left open\x1b[0;0m");
+        }
+        if let Some(path) = storage {
+            if let Err(err) = std::fs::write(path, emu.export_storage()) {
+                println!("\x1b[1;31mError: Cannot write storage to {} (Returns error \"{}\")\x1b[0;0m", path, err);
+            }
+        }
+        if let Some(report) = profile {
+            let report = emu.profile_report(report);
+            match profile_out {
+                Some(path) => if let Err(err) = std::fs::write(path, report) {
+                    println!("\x1b[1;31mError: Cannot write profile to {} (Returns error \"{}\")\x1b[0;0m", path, err);
+                },
+                None => print!("{}", report),
+            }
+        }
     }
 
     #[cfg(feature = "bot")] {
@@ -59,7 +430,9 @@ pub fn clear_text() {
 }
 
 pub fn in_text() -> String {
-    "".to_owned()
+    let mut text = String::new();
+    std::io::stdin().read_line(&mut text).unwrap_or(0);
+    text
 }
 
 pub fn out_text(text: &str) {
@@ -67,25 +440,6 @@ pub fn out_text(text: &str) {
 }
 
 
-static mut RAND_SEED: u64 = 0;
-
-pub fn rand() -> u64 {
-    unsafe {
-        let mut x = RAND_SEED;
-        if x == 0 {x = now() as u64;}
-        x ^= x << 13;
-        x ^= x >> 7;
-        x ^= x << 17;
-        RAND_SEED = x;
-        x
-    }
-}
-
-pub fn srand(seed: u64) {
-    unsafe {
-        RAND_SEED = seed;
-    }
-}
 
 pub fn out_err(out: &mut String, error: &emulator::errorcontext::Error, lineno: &String, line: &str, col: usize) {
     use std::fmt::Write;
@@ -126,22 +480,33 @@ pub fn out_err(out: &mut String, error: &emulator::errorcontext::Error, lineno:
     }
 }
 
-pub fn out_emu_err(out: &mut String, error: &emulator::emulator::EmulatorErrorKind, lineno: &String, line: &str) {
+pub fn out_emu_err(out: &mut String, error: &emulator::emulator::EmulatorErrorKind, lineno: &String, line: &str, col: usize, span: &str) {
     use std::fmt::Write;
     use crate::emulator::errorcontext::*;
-    if !cfg!(feature = "bot") {
-        writeln!(out, "\x1b[1;31mError: {}\x1b[0;0m", error).unwrap();
-        writeln!(out, "\t{}| {}", 
-            lineno, &line.split_at(get_indent_level(line)).1.replace("\t", " ")
-        ).unwrap();
-    } else {
-        writeln!(out, "\x1b[1;31mError: {}\x1b[0;0m", error).unwrap();
-        writeln!(out, "\t{}| {}", 
-            lineno, &line.split_at(get_indent_level(line)).1.replace("\t", " ")
-        ).unwrap();
-    }
+    writeln!(out, "\x1b[1;31mError: {}\x1b[0;0m", error).unwrap();
+    writeln!(out, "\t{}| {}", 
+        lineno, &line.split_at(get_indent_level(line)).1.replace("\t", " ")
+    ).unwrap();
+    writeln!(out, "\t{}| {}{}",
+        " ".repeat(str_width(lineno)),
+        &" ".repeat(col.saturating_sub(get_indent_level(line))),
+        &"^".repeat(str_width(span).max(1))
+    ).unwrap();
 }
 
+pub fn out_emu_warn(out: &mut String, error: &emulator::emulator::EmulatorErrorKind, lineno: &String, line: &str, col: usize, span: &str) {
+    use std::fmt::Write;
+    use crate::emulator::errorcontext::*;
+    writeln!(out, "\x1b[1;33mWarning: {}\x1b[0;0m", error).unwrap();
+    writeln!(out, "\t{}| {}", 
+        lineno, &line.split_at(get_indent_level(line)).1.replace("\t", " ")
+    ).unwrap();
+    writeln!(out, "\t{}| {}{}",
+        " ".repeat(str_width(lineno)),
+        &" ".repeat(col.saturating_sub(get_indent_level(line))),
+        &"^".repeat(str_width(span).max(1))
+    ).unwrap();
+}
 
 pub fn out_span(text: &str, _class_name: &str) {
     println!(">{}", text);
@@ -168,6 +533,9 @@ pub fn clear_screen() {
 
 }
 
+// the cli renders a wav at the end instead
+pub fn out_sound(_cycle: f64, _note: u32, _instrument: u32) {}
+
 pub fn out_linenumber(_: &str) {}
 
 #[allow(dead_code)]